@@ -1,5 +1,7 @@
 use all_term::Key;
 
+use crate::sort::SortKey;
+
 /// Describes a complete operation that the user can perform.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Action {
@@ -25,15 +27,128 @@ pub enum Action {
     /// preferences.
     Activate,
 
-    /// Deletes the selected entries.
+    /// Opens the selected entries in the user's editor, taking over the
+    /// terminal until it exits.
+    Edit,
+
+    /// Shows the selected files in the user's pager, taking over the terminal
+    /// until it exits.
+    View,
+
+    /// Moves the selected entries to the trash.
+    Trash,
+
+    /// Permanently deletes the selected entries.
     Delete,
 
+    /// Opens the trash browser.
+    OpenTrash,
+
+    /// Moves the entry selected in the trash browser back to where it was.
+    RestoreFromTrash,
+
+    /// Marks or unmarks the entry under the cursor, then moves down. While a
+    /// visual range is active, marks the whole range instead.
+    ToggleSelection,
+
+    /// Marks every unmarked entry in this directory and unmarks the rest.
+    InvertSelection,
+
+    /// Unmarks every entry in this directory.
+    ClearSelection,
+
+    /// Starts selecting a contiguous range of entries, anchored at the cursor.
+    StartVisual,
+
     /// Creates a file here.
     CreateFile(String),
 
     /// Creates a directory here.
     CreateDirectory(String),
 
+    /// Renames the selected entries. When there are several, `{}` in the
+    /// pattern is replaced with an entry's current name and `{n}` with its
+    /// position in the selection. A single entry is given the pattern as its
+    /// name exactly. Renaming onto an entry that already exists is refused
+    /// unless `overwrite` is set.
+    Rename {
+        pattern: String,
+        overwrite: bool,
+    },
+
+    /// Puts the selected entries on the clipboard to be copied by `Paste`.
+    /// When nothing is marked, a count takes that many entries starting at
+    /// the cursor instead of just the one under it.
+    Yank(u64),
+
+    /// Puts the selected entries on the clipboard to be moved by `Paste`,
+    /// counting entries like `Yank`.
+    Cut(u64),
+
+    /// Copies or moves the entries on the clipboard into this directory.
+    Paste(ConflictResolution),
+
+    /// Reverses the last change made to the filesystem.
+    Undo,
+
+    /// Makes the last undone change again.
+    Redo,
+
+    /// Makes the last change again, like creating, renaming, trashing,
+    /// pasting, or running a shell command, against whatever is selected now.
+    /// Changes to entries take `count` entries starting at the cursor when
+    /// nothing is marked, like `Yank`. Pastes are made `count` times, and
+    /// creates make `count` entries, numbered when the name is taken.
+    /// Trashing again doesn't ask first, since it can be undone.
+    Repeat(u64),
+
+    /// Goes back to the directory that was visited before this one.
+    HistoryBack,
+
+    /// Goes forward again after going back through the directory history.
+    HistoryForward,
+
+    /// Goes to the user's home directory.
+    GoHome,
+
+    /// Bookmarks the current directory under the given letter.
+    SetBookmark(char),
+
+    /// Goes to the directory bookmarked under the given letter.
+    JumpToBookmark(char),
+
+    /// Forgets the bookmark with the given letter.
+    DeleteBookmark(char),
+
+    /// Sorts the listing by the given key.
+    SortBy(SortKey),
+
+    /// Flips the order of the listing.
+    ToggleSortReverse,
+
+    /// Switches between keeping directories above files and mixing them in.
+    ToggleDirectoriesFirst,
+
+    /// Shows or hides entries whose names start with a `.`.
+    ToggleHidden,
+
+    /// Starts looking for files anywhere under the current directory.
+    OpenFinder,
+
+    /// Ranks the files found by the finder against new text.
+    FinderQuery(String),
+
+    /// Goes to the file selected in the finder, putting the cursor on it.
+    OpenFinderResult,
+
+    /// Goes to the file that the selected `:grep` result is in, putting the
+    /// cursor on it.
+    OpenGrepResult,
+
+    /// Opens the file that the selected `:grep` result is in with the user's
+    /// editor, at the line that matched.
+    EditGrepResult,
+
     /// Refreshes the entire application view, including refreshing the output
     /// and the directories being browsed.
     Refresh,
@@ -42,6 +157,18 @@ pub enum Action {
 
     FindNext,
 
+    /// Moves to the previous entry matching the find target, wrapping around
+    /// to the bottom.
+    FindPrevious,
+
+    /// Switches to the next way of matching names in the find and filter
+    /// prompts.
+    CycleFindMode,
+
+    /// Narrows the listing down to entries matching the given text. An empty
+    /// filter shows everything again.
+    Filter(String),
+
     /// Run a command issued by the command bar.
     RunCommand(String),
 
@@ -52,3 +179,17 @@ pub enum Action {
     /// A fallback action to indicate to the user why a key doesn't do anything.
     Unknown(Key),
 }
+
+/// What to do when an entry being pasted has the same name as an entry that's
+/// already in the directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictResolution {
+    /// Replace the existing entry.
+    Overwrite,
+
+    /// Leave the existing entry alone and don't paste this one.
+    Skip,
+
+    /// Paste the entry under a new name, like `name (1).ext`.
+    AutoRename,
+}
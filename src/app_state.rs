@@ -1,11 +1,32 @@
 use std::{
-    cmp::Ordering,
-    fs::{self, File},
-    path::PathBuf,
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    fs::{self, OpenOptions},
+    io, mem,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    process::ExitStatus,
     thread,
 };
 
-use crate::action::Action;
+use crate::{
+    action::{Action, ConflictResolution},
+    bookmarks::Bookmarks,
+    command::Command,
+    error::AppError,
+    file_ops,
+    filter::EntryFilter,
+    finder::Finder,
+    foreground::ForegroundCommand,
+    grep::Grep,
+    journal::{Change, Journal},
+    matcher::{MatchMode, Matcher},
+    metadata::{EntryMetadata, OwnerNames},
+    overlay::{self, Overlay, OverlayContent},
+    settings::Settings,
+    shell::{self, PendingCommand, Placeholders},
+    trash::Trash,
+};
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum FileEntryKind {
@@ -14,44 +35,37 @@ pub enum FileEntryKind {
     File,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FileEntry {
     pub kind: FileEntryKind,
     pub display: String,
     pub path: PathBuf,
+    pub metadata: EntryMetadata,
 }
 
-impl PartialOrd for FileEntry {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipboardMode {
+    Copy,
+    Move,
 }
 
-impl Ord for FileEntry {
-    fn cmp(&self, other: &Self) -> Ordering {
-        match (self.kind, other.kind) {
-            (FileEntryKind::Parent, _) => Ordering::Less,
-            (_, FileEntryKind::Parent) => Ordering::Greater,
-            (FileEntryKind::Directory, FileEntryKind::File) => Ordering::Less,
-            (FileEntryKind::File, FileEntryKind::Directory) => Ordering::Greater,
-            _ => self
-                .display
-                .to_lowercase()
-                .cmp(&other.display.to_lowercase()),
-        }
-    }
+/// Entries that were yanked or cut, waiting to be pasted somewhere.
+#[derive(Debug, Clone)]
+pub struct Clipboard {
+    pub mode: ClipboardMode,
+    pub paths: Vec<PathBuf>,
 }
 
-// TODO: Make this a configurable setting
-pub fn find_should_match(entry_display: &str, find_target: &str) -> bool {
-    if find_target.is_empty() {
-        return false;
-    }
-
-    let entry_lower = entry_display.to_lowercase();
-    let find_lower = find_target.to_lowercase();
+/// How many directories are remembered in each direction of the history.
+const MAX_HISTORY: usize = 100;
 
-    entry_lower.starts_with(&find_lower)
+/// Where the cursor was in a directory when it was last left, so that it can
+/// be put back when the directory is visited again.
+#[derive(Debug, Clone)]
+struct DirectoryPosition {
+    entry: Option<PathBuf>,
+    cursor: usize,
+    window_start: usize,
 }
 
 #[derive(Debug)]
@@ -63,10 +77,74 @@ pub struct AppState {
     pub entry_window_start: usize,
     pub find_target: String,
     pub no_find_match: bool,
+
+    /// Only entries matching this are listed, unless it's empty.
+    pub filter: String,
+
+    pub last_error: Option<AppError>,
+
+    /// Something to tell the user that isn't an error, like the exit status
+    /// of a shell command. Like `last_error`, it's cleared by the next action.
+    pub status_message: Option<String>,
+    pub clipboard: Option<Clipboard>,
+
+    /// Entries that have been marked by the user, keyed by the directory
+    /// they're in so that marks survive browsing elsewhere and coming back.
+    pub selections: HashMap<PathBuf, HashSet<PathBuf>>,
+
+    /// Where the visual range started, if one is being selected. The range
+    /// spans from here to the cursor.
+    pub visual_anchor: Option<usize>,
+
+    /// A list being shown on top of the directory listing, like the trash.
+    pub overlay: Option<Overlay>,
+
+    /// The fuzzy finder, while it's open, along with the text it's ranking
+    /// files against.
+    pub finder: Option<Finder>,
+    pub finder_query: String,
+
+    /// The `:grep` search filling the overlay, while it's open.
+    pub grep: Option<Grep>,
+
+    /// A program that should take over the terminal, like an editor. The main
+    /// loop runs it, then calls `finish_foreground_command`.
+    pub foreground_command: Option<ForegroundCommand>,
+
+    /// A command from `:!` waiting to be run. Commands can take a while, so
+    /// the main loop shows that it's running before calling
+    /// `run_shell_command`.
+    pub shell_command: Option<PendingCommand>,
+
+    /// Changes made to the filesystem, so they can be undone.
+    pub journal: Journal,
+
+    /// Directories to return to with `HistoryBack` and `HistoryForward`, the
+    /// most recent last.
+    pub history_back: Vec<PathBuf>,
+    pub history_forward: Vec<PathBuf>,
+
+    pub settings: Settings,
+
+    /// Set by `:q`, telling the main loop to exit.
+    pub should_quit: bool,
+
+    positions: HashMap<PathBuf, DirectoryPosition>,
+
+    /// The last change that `.` makes again. Unlike `last_action`, it isn't
+    /// replaced by moving around or anything else that changes nothing.
+    last_change: Option<Action>,
+
+    /// Names of users and groups for the owner column, only loaded once the
+    /// column is shown.
+    owner_names: Option<OwnerNames>,
+
+    /// Every entry in the working directory, before `filter` is applied.
+    unfiltered_entries: Vec<FileEntry>,
 }
 
 impl AppState {
-    pub fn new(start_dir: PathBuf) -> AppState {
+    pub fn new(start_dir: PathBuf, settings: Settings) -> AppState {
         let mut state = AppState {
             last_action: None,
             working_directory: PathBuf::new(),
@@ -75,52 +153,254 @@ impl AppState {
             entry_window_start: 0,
             find_target: String::new(),
             no_find_match: false,
+            filter: String::new(),
+            last_error: None,
+            status_message: None,
+            clipboard: None,
+            selections: HashMap::new(),
+            visual_anchor: None,
+            overlay: None,
+            finder: None,
+            finder_query: String::new(),
+            grep: None,
+            foreground_command: None,
+            shell_command: None,
+            journal: Journal::new(),
+            history_back: Vec::new(),
+            history_forward: Vec::new(),
+            settings,
+            should_quit: false,
+            positions: HashMap::new(),
+            last_change: None,
+            owner_names: None,
+            unfiltered_entries: Vec::new(),
         };
 
-        state.set_working_directory(start_dir);
+        if let Err(error) = state.set_working_directory(start_dir.clone()) {
+            // There's nowhere to fall back to, so show the empty directory
+            // along with the reason why it's empty.
+            state.working_directory = start_dir;
+            state.last_error = Some(error);
+        }
 
         state
     }
 
-    fn refresh_working_directory(&mut self) {
-        self.entries.clear();
+    fn read_entries(&mut self, directory: &Path) -> Result<Vec<FileEntry>, AppError> {
+        let read_error = |source| AppError::ReadDirectory {
+            path: directory.to_path_buf(),
+            source,
+        };
+
+        let settings = &self.settings;
+
+        if settings.columns.owner && self.owner_names.is_none() {
+            self.owner_names = Some(OwnerNames::load());
+        }
+
+        let names = self.owner_names.as_ref();
+        let filter = EntryFilter::new(directory, settings);
+        let mut entries = Vec::new();
 
-        if let Some(parent) = self.working_directory.parent() {
-            self.entries.push(FileEntry {
+        if let Some(parent) = directory.parent() {
+            entries.push(FileEntry {
                 kind: FileEntryKind::Parent,
                 display: "..".to_string(),
                 path: parent.to_path_buf(),
+                metadata: EntryMetadata::read(parent, names),
             });
         }
 
-        for entry in fs::read_dir(&self.working_directory).unwrap() {
-            let entry = entry.unwrap();
+        for entry in fs::read_dir(directory).map_err(read_error)? {
+            let entry = entry.map_err(read_error)?;
             let path = entry.path();
+            let is_directory = path.is_dir();
+
+            if !filter.is_visible(&path, is_directory) {
+                continue;
+            }
+
             let mut display = path.file_name().unwrap().to_string_lossy().to_string();
             let mut kind = FileEntryKind::File;
 
-            if path.is_dir() {
+            if is_directory {
                 kind = FileEntryKind::Directory;
                 display.push_str("/");
             }
 
-            self.entries.push(FileEntry {
+            entries.push(FileEntry {
                 kind,
                 display,
+                metadata: EntryMetadata::read(&path, names),
                 path,
             });
         }
 
-        self.entries.sort();
-        self.cursor = self.cursor.min(self.entries.len());
+        entries.sort_by(|a, b| settings.sort.compare(a, b));
+
+        Ok(entries)
+    }
+
+    fn refresh_working_directory(&mut self) -> Result<(), AppError> {
+        let directory = self.working_directory.clone();
+        self.unfiltered_entries = self.read_entries(&directory)?;
+        self.apply_filter();
+
+        // Forget about marks on entries that don't exist anymore.
+        if let Some(selection) = self.selections.get_mut(&self.working_directory) {
+            let entries = &self.unfiltered_entries;
+            selection.retain(|path| entries.iter().any(|entry| entry.path == *path));
+        }
+
+        Ok(())
+    }
+
+    /// Sorts the entries again after the sort order changes.
+    fn sort_entries(&mut self) {
+        let sort = self.settings.sort;
+
+        self.unfiltered_entries.sort_by(|a, b| sort.compare(a, b));
+        self.visual_anchor = None;
+        self.apply_filter();
+    }
+
+    /// Rebuilds the listing from every entry in the directory, leaving out
+    /// the ones that don't match the filter. The parent directory is always
+    /// listed so there's a way out.
+    ///
+    /// The cursor stays on the same entry if it's still listed, since entries
+    /// can come and go above it.
+    fn apply_filter(&mut self) {
+        let cursor_path = self
+            .entries
+            .get(self.cursor)
+            .map(|entry| entry.path.clone());
+
+        let matcher = Matcher::new(self.settings.find_mode, &self.filter);
+        self.entries = self
+            .unfiltered_entries
+            .iter()
+            .filter(|entry| {
+                entry.kind == FileEntryKind::Parent
+                    || matcher.is_empty()
+                    || matcher.is_match(&entry.display)
+            })
+            .cloned()
+            .collect();
+
+        if let Some(path) = cursor_path {
+            if let Some(index) = self.entries.iter().position(|entry| entry.path == path) {
+                self.cursor = index;
+            }
+        }
+
+        self.cursor = self.cursor.min(self.entries.len().saturating_sub(1));
+        self.visual_anchor = self.visual_anchor.map(|anchor| anchor.min(self.cursor));
+    }
+
+    /// Moves to the given directory, remembering where we came from in the
+    /// history. If the directory can't be read, the current directory is left
+    /// untouched.
+    pub fn set_working_directory(&mut self, path: PathBuf) -> Result<(), AppError> {
+        let previous = self.change_directory(path)?;
+
+        // The very first directory has nowhere to come back to.
+        if !previous.as_os_str().is_empty() && previous != self.working_directory {
+            AppState::push_history(&mut self.history_back, previous);
+            self.history_forward.clear();
+        }
+
+        Ok(())
+    }
+
+    fn push_history(stack: &mut Vec<PathBuf>, path: PathBuf) {
+        stack.push(path);
+
+        if stack.len() > MAX_HISTORY {
+            stack.remove(0);
+        }
+    }
+
+    /// Switches directories without touching the history, returning the
+    /// directory that we left.
+    fn change_directory(&mut self, path: PathBuf) -> Result<PathBuf, AppError> {
+        let entries = self.read_entries(&path)?;
+
+        if !self.working_directory.as_os_str().is_empty() {
+            self.positions.insert(
+                self.working_directory.clone(),
+                DirectoryPosition {
+                    entry: self
+                        .entries
+                        .get(self.cursor)
+                        .map(|entry| entry.path.clone()),
+                    cursor: self.cursor,
+                    window_start: self.entry_window_start,
+                },
+            );
+        }
+
+        let previous = mem::replace(&mut self.working_directory, path);
+        self.filter.clear();
+        self.entries = entries.clone();
+        self.unfiltered_entries = entries;
+        self.visual_anchor = None;
+        self.restore_position(&previous);
+
+        Ok(previous)
     }
 
-    pub fn set_working_directory(&mut self, path: PathBuf) {
+    /// Puts the cursor back where it was when this directory was last left.
+    /// When coming up out of a child directory, the cursor lands on that child.
+    fn restore_position(&mut self, previous: &Path) {
+        let position = self.positions.get(&self.working_directory).cloned();
+
         self.cursor = 0;
         self.entry_window_start = 0;
-        self.working_directory = path;
 
-        self.refresh_working_directory();
+        if let Some(position) = &position {
+            self.entry_window_start = position.window_start;
+            self.cursor = position
+                .entry
+                .as_ref()
+                .and_then(|path| self.entries.iter().position(|entry| entry.path == *path))
+                .unwrap_or(position.cursor);
+        }
+
+        if previous.parent() == Some(self.working_directory.as_path()) {
+            if let Some(index) = self.entries.iter().position(|entry| entry.path == previous) {
+                self.cursor = index;
+            }
+        }
+
+        self.cursor = self.cursor.min(self.entries.len().saturating_sub(1));
+        self.entry_window_start = self.entry_window_start.min(self.cursor);
+    }
+
+    fn history_back(&mut self) -> Result<(), AppError> {
+        let path = match self.history_back.pop() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        // A directory that can't be visited anymore is dropped from the
+        // history, so that the next jump can get past it.
+        let previous = self.change_directory(path)?;
+        AppState::push_history(&mut self.history_forward, previous);
+
+        Ok(())
+    }
+
+    fn history_forward(&mut self) -> Result<(), AppError> {
+        let path = match self.history_forward.pop() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let previous = self.change_directory(path)?;
+        AppState::push_history(&mut self.history_back, previous);
+
+        Ok(())
     }
 
     pub fn open_file(&self, path: PathBuf) {
@@ -131,18 +411,41 @@ impl AppState {
         });
     }
 
+    pub fn find_matcher(&self) -> Matcher {
+        Matcher::new(self.settings.find_mode, &self.find_target)
+    }
+
+    /// Lists the entries matching the find target in the order that
+    /// `FindNext` visits them: best first for fuzzy matches, and top to
+    /// bottom otherwise.
+    fn find_order(&self, matcher: &Matcher) -> Vec<usize> {
+        let mut matches: Vec<(usize, i64)> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entry)| {
+                matcher
+                    .find(&entry.display)
+                    .map(|found| (index, found.score))
+            })
+            .collect();
+
+        if matcher.mode() == MatchMode::Fuzzy {
+            // The sort is stable, so equally good matches stay top to bottom.
+            matches.sort_by_key(|&(_, score)| Reverse(score));
+        }
+
+        matches.into_iter().map(|(index, _)| index).collect()
+    }
+
     fn perform_find(&mut self) {
         if self.find_target.is_empty() {
             return;
         }
 
-        let found_index = self
-            .entries
-            .iter()
-            .enumerate()
-            .find(|(_, entry)| find_should_match(&entry.display, &self.find_target));
+        let found_index = self.find_order(&self.find_matcher()).first().cloned();
 
-        if let Some((index, _)) = found_index {
+        if let Some(index) = found_index {
             self.cursor = index;
             self.no_find_match = false;
         } else {
@@ -151,17 +454,51 @@ impl AppState {
     }
 
     fn perform_find_next(&mut self) {
-        let mut found_index = None;
-        let first_range = (self.cursor + 1)..self.entries.len();
-        let second_range = 0..=self.cursor;
-        let iter = first_range.chain(second_range);
+        self.perform_find_step(true);
+    }
+
+    fn perform_find_previous(&mut self) {
+        self.perform_find_step(false);
+    }
+
+    /// Moves to the next or previous match, wrapping around at either end.
+    fn perform_find_step(&mut self, forward: bool) {
+        if self.entries.is_empty() {
+            return;
+        }
+
+        let matcher = self.find_matcher();
+        let mut order = self.find_order(&matcher);
+
+        if !forward {
+            order.reverse();
+        }
 
-        for i in iter {
-            if find_should_match(&self.entries[i].display, &self.find_target) {
-                found_index = Some(i);
-                break;
+        // Move on from the cursor's place in the order. If the cursor isn't
+        // on a match, fuzzy matching starts over from the best match, and
+        // everything else carries on from the nearest match in the direction
+        // we're going.
+        let found_index = match order.iter().position(|&index| index == self.cursor) {
+            Some(position) => order.get(position + 1).or_else(|| order.first()),
+            None if matcher.mode() == MatchMode::Fuzzy => {
+                if forward {
+                    order.first()
+                } else {
+                    order.last()
+                }
             }
+            None => order
+                .iter()
+                .find(|&&index| {
+                    if forward {
+                        index > self.cursor
+                    } else {
+                        index < self.cursor
+                    }
+                })
+                .or_else(|| order.first()),
         }
+        .cloned();
 
         if let Some(index) = found_index {
             self.cursor = index;
@@ -171,106 +508,1711 @@ impl AppState {
         }
     }
 
-    fn find_entry_with_file_name(&self, name: &str) -> Option<usize> {
+    pub fn is_selected(&self, entry: &FileEntry) -> bool {
+        match self.selections.get(&self.working_directory) {
+            Some(selection) => selection.contains(&entry.path),
+            None => false,
+        }
+    }
+
+    pub fn visual_range(&self) -> Option<RangeInclusive<usize>> {
+        if self.entries.is_empty() {
+            return None;
+        }
+
+        self.visual_anchor
+            .map(|anchor| anchor.min(self.cursor)..=anchor.max(self.cursor))
+    }
+
+    pub fn in_visual_range(&self, index: usize) -> bool {
+        match self.visual_range() {
+            Some(range) => range.start() <= &index && &index <= range.end(),
+            None => false,
+        }
+    }
+
+    /// The entries that operations like delete should apply to: the visual
+    /// range if there is one, then every marked entry in this directory, or
+    /// the entry under the cursor if nothing is marked.
+    pub fn selected_entries(&self) -> Vec<&FileEntry> {
+        if let Some(range) = self.visual_range() {
+            return self.entries[range]
+                .iter()
+                .filter(|entry| entry.kind != FileEntryKind::Parent)
+                .collect();
+        }
+
+        let marked: Vec<&FileEntry> = self
+            .entries
+            .iter()
+            .filter(|entry| self.is_selected(entry))
+            .collect();
+
+        if !marked.is_empty() {
+            return marked;
+        }
+
         self.entries
+            .get(self.cursor)
+            .filter(|entry| entry.kind != FileEntryKind::Parent)
+            .into_iter()
+            .collect()
+    }
+
+    fn selected_paths(&self) -> Vec<PathBuf> {
+        self.selected_entries()
             .iter()
-            .enumerate()
-            .find(|(_, entry)| match entry.path.file_name() {
-                Some(file_name) => match file_name.to_str() {
-                    Some(file_name) => file_name == name,
-                    None => false,
-                },
-                None => false,
-            })
-            .map(|(index, _)| index)
+            .map(|entry| entry.path.clone())
+            .collect()
     }
 
-    pub fn process_action(&mut self, action: Action) {
-        self.last_action = Some(action.clone());
+    /// The selected entries, or if nothing is marked and there's a count,
+    /// that many entries starting at the cursor, like `3dd` in Vim.
+    fn counted_paths(&self, count: u64) -> Vec<PathBuf> {
+        let is_marked = self.visual_range().is_some()
+            || self.entries.iter().any(|entry| self.is_selected(entry));
 
-        match action {
-            Action::Up(count) => {
-                for _ in 0..count {
-                    if self.cursor > 0 {
-                        self.cursor -= 1;
-                    }
-                }
-            }
-            Action::Down(count) => {
-                for _ in 0..count {
-                    if self.cursor < self.entries.len() - 1 {
-                        self.cursor += 1;
-                    }
-                }
+        if count <= 1 || is_marked {
+            return self.selected_paths();
+        }
+
+        self.entries
+            .iter()
+            .skip(self.cursor)
+            .take(count as usize)
+            .filter(|entry| entry.kind != FileEntryKind::Parent)
+            .map(|entry| entry.path.clone())
+            .collect()
+    }
+
+    fn toggle_selection(&mut self) {
+        let path = match self.entries.get(self.cursor) {
+            Some(entry) if entry.kind != FileEntryKind::Parent => entry.path.clone(),
+            _ => return,
+        };
+
+        let selection = self
+            .selections
+            .entry(self.working_directory.clone())
+            .or_default();
+
+        if !selection.remove(&path) {
+            selection.insert(path);
+        }
+    }
+
+    fn invert_selection(&mut self) {
+        let selection = self
+            .selections
+            .entry(self.working_directory.clone())
+            .or_default();
+
+        for entry in &self.entries {
+            if entry.kind != FileEntryKind::Parent && !selection.remove(&entry.path) {
+                selection.insert(entry.path.clone());
             }
-            Action::Top => {
-                self.cursor = 0;
+        }
+    }
+
+    fn mark_visual_range(&mut self) {
+        let paths: Vec<PathBuf> = self
+            .selected_entries()
+            .iter()
+            .map(|entry| entry.path.clone())
+            .collect();
+
+        self.selections
+            .entry(self.working_directory.clone())
+            .or_default()
+            .extend(paths);
+
+        self.visual_anchor = None;
+    }
+
+    fn clear_selection(&mut self) {
+        self.selections.remove(&self.working_directory);
+        self.visual_anchor = None;
+    }
+
+    /// Works out the new path of each entry when renamed to `text`. A single
+    /// entry is given exactly that name, so that names with braces in them
+    /// can be typed, while several entries are renamed using it as a pattern.
+    fn rename_targets(&self, paths: &[PathBuf], text: &str) -> Vec<(PathBuf, PathBuf)> {
+        match paths {
+            [path] => vec![(path.clone(), self.working_directory.join(text))],
+            _ => self.pattern_targets(paths, text),
+        }
+    }
+
+    /// Works out the new path of each entry when renamed using the given
+    /// pattern. In the pattern, `{}` stands for the entry's current name and
+    /// `{n}` for its position in `paths`, starting at 1.
+    fn pattern_targets(&self, paths: &[PathBuf], pattern: &str) -> Vec<(PathBuf, PathBuf)> {
+        paths
+            .iter()
+            .enumerate()
+            .filter_map(|(index, path)| {
+                let name = path.file_name()?.to_string_lossy().to_string();
+                let new_name = pattern
+                    .replace("{n}", &(index + 1).to_string())
+                    .replace("{}", &name);
+
+                Some((path.clone(), self.working_directory.join(new_name)))
+            })
+            .collect()
+    }
+
+    fn rename_collides(from: &Path, to: &Path) -> bool {
+        // Changing only the case of a name shouldn't count as a collision on
+        // case-insensitive filesystems, where both names are the same entry.
+        // Elsewhere, they can be two different entries.
+        let same_name = match (from.file_name(), to.file_name()) {
+            (Some(from_name), Some(to_name)) => {
+                from.parent() == to.parent()
+                    && from_name.to_string_lossy().to_lowercase()
+                        == to_name.to_string_lossy().to_lowercase()
             }
-            Action::Bottom => {
-                self.cursor = self.entries.len() - 1;
+            _ => false,
+        };
+
+        let same_entry = same_name && file_ops::is_same_entry(from, to);
+
+        !same_entry && file_ops::path_exists(to)
+    }
+
+    /// Whether renaming the selected entries using `pattern` would replace any
+    /// different entries that already exist.
+    pub fn rename_would_overwrite(&self, pattern: &str) -> bool {
+        self.rename_targets(&self.selected_paths(), pattern)
+            .iter()
+            .any(|(from, to)| AppState::rename_collides(from, to))
+    }
+
+    fn rename_selected(&mut self, pattern: &str, overwrite: bool) -> Result<(), AppError> {
+        if pattern.is_empty() {
+            return Ok(());
+        }
+
+        let targets = self.rename_targets(&self.selected_paths(), pattern);
+        self.rename_all(&targets, overwrite)
+    }
+
+    /// Renames several entries at once as one change that can be undone.
+    fn rename_all(
+        &mut self,
+        targets: &[(PathBuf, PathBuf)],
+        overwrite: bool,
+    ) -> Result<(), AppError> {
+        // Two entries can't take the same name, and an entry can't be replaced
+        // while it's still waiting to be renamed itself.
+        for (index, (_, to)) in targets.iter().enumerate() {
+            let clashes =
+                targets
+                    .iter()
+                    .enumerate()
+                    .any(|(other_index, (other_from, other_to))| {
+                        other_index != index && (other_to == to || other_from == to)
+                    });
+
+            if clashes {
+                return Err(AppError::AlreadyExists { path: to.clone() });
             }
-            Action::Activate => {
-                let entry = &self.entries[self.cursor];
+        }
 
-                match entry.kind {
-                    FileEntryKind::Directory | FileEntryKind::Parent => {
-                        self.set_working_directory(entry.path.clone());
-                    }
-                    FileEntryKind::File => {
-                        self.open_file(entry.path.clone());
-                    }
-                }
+        let mut changes = Vec::new();
+        let result = AppState::rename_paths(targets, overwrite, &mut changes);
+
+        self.journal.record(changes);
+        self.clear_selection();
+        self.refresh_working_directory()?;
+        result?;
+
+        // Keep the cursor on the entry that was just renamed.
+        if let Some(name) = targets.last().and_then(|(_, to)| to.file_name()) {
+            let new_cursor = self
+                .find_entry_with_file_name(&name.to_string_lossy())
+                .unwrap_or(self.cursor);
+
+            self.cursor = new_cursor;
+        }
+
+        Ok(())
+    }
+
+    fn rename_paths(
+        targets: &[(PathBuf, PathBuf)],
+        overwrite: bool,
+        changes: &mut Vec<Change>,
+    ) -> Result<(), AppError> {
+        for (from, to) in targets {
+            if from == to {
+                continue;
             }
-            Action::Delete => {
-                let entry = &self.entries[self.cursor];
 
-                match entry.kind {
-                    FileEntryKind::Directory => {
-                        fs::remove_dir_all(&entry.path)
-                            .expect("Could not remove directory and its contents!");
-                    }
-                    FileEntryKind::File => {
-                        fs::remove_file(&entry.path).expect("Could not remove file!");
-                    }
-                    FileEntryKind::Parent => {}
+            if AppState::rename_collides(from, to) {
+                if !overwrite {
+                    return Err(AppError::AlreadyExists { path: to.clone() });
                 }
 
-                self.refresh_working_directory();
+                changes.push(AppState::trash_path(to)?);
             }
-            Action::CreateFile(name) => {
-                let path = self.working_directory.join(&name);
-                File::create(path).expect("Could not create file!");
 
-                self.refresh_working_directory();
+            fs::rename(from, to).map_err(|source| AppError::Rename {
+                path: from.clone(),
+                source,
+            })?;
 
-                // Move the cursor to highlight the new entry.
-                let new_cursor = self.find_entry_with_file_name(&name).unwrap_or(self.cursor);
+            changes.push(Change::Move {
+                from: from.clone(),
+                to: to.clone(),
+            });
+        }
 
-                self.cursor = new_cursor;
-            }
-            Action::CreateDirectory(name) => {
-                let path = self.working_directory.join(&name);
-                fs::create_dir(path).expect("Could not create directory!");
+        Ok(())
+    }
 
-                self.refresh_working_directory();
+    /// Moves an entry to the trash, returning how to undo that. Entries that
+    /// get overwritten go to the trash too, so that overwriting can be undone.
+    fn trash_path(path: &Path) -> Result<Change, AppError> {
+        let trash = Trash::home().ok_or(AppError::NoTrash)?;
+        let item = trash.put(path).map_err(|source| AppError::Trash {
+            path: path.to_path_buf(),
+            source,
+        })?;
 
-                // Move the cursor to highlight the new entry.
-                let new_cursor = self.find_entry_with_file_name(&name).unwrap_or(self.cursor);
+        Ok(Change::Trash { item })
+    }
 
-                self.cursor = new_cursor;
-            }
-            Action::Refresh => {
-                self.refresh_working_directory();
-            }
-            Action::Find(target) => {
-                self.find_target = target;
+    fn fill_clipboard(&mut self, mode: ClipboardMode, count: u64) {
+        let paths = self.counted_paths(count);
 
-                self.perform_find();
+        if !paths.is_empty() {
+            self.clipboard = Some(Clipboard { mode, paths });
+            self.clear_selection();
+        }
+    }
+
+    fn delete_selected(&mut self) -> Result<(), AppError> {
+        let paths = self.selected_paths();
+        self.delete_paths(&paths)
+    }
+
+    fn delete_paths(&mut self, paths: &[PathBuf]) -> Result<(), AppError> {
+        let result = paths.iter().try_for_each(|path| {
+            // Symlinks to directories are removed like files, leaving what
+            // they point to alone.
+            let is_directory = fs::symlink_metadata(path)
+                .map(|metadata| metadata.is_dir())
+                .unwrap_or(false);
+
+            if is_directory {
+                fs::remove_dir_all(path).map_err(|source| AppError::RemoveDirectory {
+                    path: path.clone(),
+                    source,
+                })
+            } else {
+                fs::remove_file(path).map_err(|source| AppError::RemoveFile {
+                    path: path.clone(),
+                    source,
+                })
             }
-            Action::FindNext => {
-                self.perform_find_next();
+        });
+
+        self.clear_selection();
+        self.refresh_working_directory()?;
+
+        result
+    }
+
+    /// Whether pasting the clipboard here would collide with any entries that
+    /// already exist.
+    pub fn paste_would_conflict(&self) -> bool {
+        let clipboard = match &self.clipboard {
+            Some(clipboard) => clipboard,
+            None => return false,
+        };
+
+        clipboard.paths.iter().any(|from| match from.file_name() {
+            Some(name) => {
+                let to = self.working_directory.join(name);
+                let is_noop_move = clipboard.mode == ClipboardMode::Move && *from == to;
+
+                !is_noop_move && file_ops::path_exists(&to)
             }
-            _ => {}
-        }
+            None => false,
+        })
+    }
+
+    fn paste(&mut self, resolution: ConflictResolution) -> Result<(), AppError> {
+        let clipboard = match self.clipboard.clone() {
+            Some(clipboard) => clipboard,
+            None => return Ok(()),
+        };
+
+        let mut changes = Vec::new();
+        let result = self.paste_paths(&clipboard, resolution, &mut changes);
+
+        self.journal.record(changes);
+
+        if clipboard.mode == ClipboardMode::Move {
+            self.clipboard = None;
+        }
+
+        // Some entries might have been pasted before an error, so the listing
+        // needs to be refreshed either way.
+        self.refresh_working_directory()?;
+
+        let last_name = result?;
+
+        // Move the cursor to highlight the last pasted entry.
+        if let Some(name) = last_name {
+            let new_cursor = self.find_entry_with_file_name(&name).unwrap_or(self.cursor);
+
+            self.cursor = new_cursor;
+        }
+
+        Ok(())
+    }
+
+    fn paste_paths(
+        &self,
+        clipboard: &Clipboard,
+        resolution: ConflictResolution,
+        changes: &mut Vec<Change>,
+    ) -> Result<Option<String>, AppError> {
+        let mut last_name = None;
+
+        for from in &clipboard.paths {
+            let name = match from.file_name() {
+                Some(name) => name,
+                None => continue,
+            };
+
+            if self.working_directory.starts_with(from) {
+                return Err(AppError::PasteIntoItself { path: from.clone() });
+            }
+
+            let mut to = self.working_directory.join(name);
+
+            if clipboard.mode == ClipboardMode::Move && *from == to {
+                continue;
+            }
+
+            if file_ops::path_exists(&to) {
+                match resolution {
+                    ConflictResolution::Skip => continue,
+                    ConflictResolution::Overwrite => {
+                        // Overwriting an entry with itself, or with something
+                        // inside of it, would destroy what's being pasted.
+                        if from.starts_with(&to) {
+                            return Err(AppError::PasteIntoItself { path: from.clone() });
+                        }
+
+                        changes.push(AppState::trash_path(&to)?);
+                    }
+                    ConflictResolution::AutoRename => {
+                        to = file_ops::unique_path(&self.working_directory, name);
+                    }
+                }
+            }
+
+            match clipboard.mode {
+                ClipboardMode::Copy => {
+                    file_ops::copy_recursive(from, &to).map_err(|source| AppError::Copy {
+                        path: from.clone(),
+                        source,
+                    })?;
+
+                    changes.push(Change::Copy {
+                        from: from.clone(),
+                        to: to.clone(),
+                    });
+                }
+                ClipboardMode::Move => {
+                    file_ops::move_path(from, &to).map_err(|source| AppError::Move {
+                        path: from.clone(),
+                        source,
+                    })?;
+
+                    changes.push(Change::Move {
+                        from: from.clone(),
+                        to: to.clone(),
+                    });
+                }
+            }
+
+            last_name = to
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string());
+        }
+
+        Ok(last_name)
+    }
+
+    fn trash_selected(&mut self) -> Result<(), AppError> {
+        let paths = self.selected_paths();
+        self.trash_paths(&paths)
+    }
+
+    fn trash_paths(&mut self, paths: &[PathBuf]) -> Result<(), AppError> {
+        let mut changes = Vec::new();
+
+        let result = paths
+            .iter()
+            .try_for_each(|path| AppState::trash_path(path).map(|change| changes.push(change)));
+
+        self.journal.record(changes);
+        self.clear_selection();
+        self.refresh_working_directory()?;
+
+        result
+    }
+
+    fn open_trash(&mut self) -> Result<(), AppError> {
+        let trash = Trash::home().ok_or(AppError::NoTrash)?;
+        let items = trash
+            .list()
+            .map_err(|source| AppError::ListTrash { source })?;
+
+        self.overlay = Some(Overlay::new(OverlayContent::Trash(items)));
+
+        Ok(())
+    }
+
+    fn restore_from_trash(&mut self) -> Result<(), AppError> {
+        let item = match &self.overlay {
+            Some(Overlay {
+                content: OverlayContent::Trash(items),
+                cursor,
+                ..
+            }) => match items.get(*cursor) {
+                Some(item) => item.clone(),
+                None => return Ok(()),
+            },
+            _ => return Ok(()),
+        };
+
+        let trash = Trash::home().ok_or(AppError::NoTrash)?;
+        trash.restore(&item).map_err(|source| AppError::Restore {
+            path: item.original_path.clone(),
+            source,
+        })?;
+
+        // Reload the trash so the list reflects what's really left in it.
+        let cursor = self.overlay.as_ref().map_or(0, |overlay| overlay.cursor);
+        self.open_trash()?;
+
+        if let Some(overlay) = &mut self.overlay {
+            overlay.cursor = cursor;
+            overlay.clamp_cursor();
+        }
+
+        self.refresh_working_directory()?;
+
+        // If the entry came back into this directory, point it out.
+        if item.original_path.parent() == Some(self.working_directory.as_path()) {
+            if let Some(name) = item.original_path.file_name() {
+                let new_cursor = self
+                    .find_entry_with_file_name(&name.to_string_lossy())
+                    .unwrap_or(self.cursor);
+
+                self.cursor = new_cursor;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The bookmark highlighted in the bookmark list, if it's open.
+    pub fn selected_bookmark(&self) -> Option<char> {
+        match &self.overlay {
+            Some(Overlay {
+                content: OverlayContent::Bookmarks(marks),
+                cursor,
+                ..
+            }) => marks.get(*cursor).map(|(letter, _)| *letter),
+            _ => None,
+        }
+    }
+
+    fn open_bookmarks(&mut self) -> Result<(), AppError> {
+        let bookmarks = Bookmarks::default_location().ok_or(AppError::NoDataDirectory)?;
+        let marks = bookmarks.load().map_err(|source| AppError::Bookmarks {
+            path: bookmarks.file_path().to_path_buf(),
+            source,
+        })?;
+
+        let cursor = self.overlay.as_ref().map_or(0, |overlay| overlay.cursor);
+        let mut overlay = Overlay::new(OverlayContent::Bookmarks(marks.into_iter().collect()));
+        overlay.cursor = cursor;
+        overlay.clamp_cursor();
+
+        self.overlay = Some(overlay);
+
+        Ok(())
+    }
+
+    fn update_bookmarks<F>(&mut self, update: F) -> Result<(), AppError>
+    where
+        F: FnOnce(&Bookmarks) -> std::io::Result<()>,
+    {
+        let bookmarks = Bookmarks::default_location().ok_or(AppError::NoDataDirectory)?;
+
+        update(&bookmarks).map_err(|source| AppError::Bookmarks {
+            path: bookmarks.file_path().to_path_buf(),
+            source,
+        })
+    }
+
+    fn jump_to_bookmark(&mut self, letter: char) -> Result<(), AppError> {
+        let bookmarks = Bookmarks::default_location().ok_or(AppError::NoDataDirectory)?;
+        let path = bookmarks
+            .get(letter)
+            .map_err(|source| AppError::Bookmarks {
+                path: bookmarks.file_path().to_path_buf(),
+                source,
+            })?
+            .ok_or(AppError::NoSuchBookmark(letter))?;
+
+        self.overlay = None;
+        self.set_working_directory(path)
+    }
+
+    fn open_finder(&mut self) {
+        self.finder = Some(Finder::start(
+            self.working_directory.clone(),
+            &self.settings,
+        ));
+        self.finder_query.clear();
+        self.update_finder_results(true);
+    }
+
+    /// Shows the finder's best matches in the overlay. When new files turn up
+    /// in the background, the cursor stays put so the list doesn't jump
+    /// around under the user.
+    fn update_finder_results(&mut self, reset_cursor: bool) {
+        let finder = match &self.finder {
+            Some(finder) => finder,
+            None => return,
+        };
+
+        let content = OverlayContent::Finder {
+            results: finder.rank(&self.finder_query),
+            searched: finder.len(),
+            done: finder.is_done(),
+        };
+
+        let cursor = match &self.overlay {
+            Some(overlay) if !reset_cursor => overlay.cursor,
+            _ => 0,
+        };
+
+        let mut overlay = Overlay::new(content);
+        overlay.cursor = cursor;
+        overlay.clamp_cursor();
+        self.overlay = Some(overlay);
+    }
+
+    /// Picks up results from work happening on other threads. Reading keys
+    /// blocks, so this only happens between key presses.
+    /// Whether the finder or a grep is still looking for more results, which
+    /// need to be picked up with `poll_background_work` as they come in.
+    pub fn has_background_work(&self) -> bool {
+        let finding = self.finder.as_ref().is_some_and(|finder| !finder.is_done());
+        let grepping = self.grep.as_ref().is_some_and(|grep| !grep.is_done());
+
+        finding || grepping
+    }
+
+    pub fn poll_background_work(&mut self) {
+        let changed = match &mut self.finder {
+            Some(finder) => finder.poll(),
+            None => false,
+        };
+
+        if changed {
+            self.update_finder_results(false);
+        }
+
+        if let (
+            Some(grep),
+            Some(Overlay {
+                content: OverlayContent::Grep { matches, done, .. },
+                ..
+            }),
+        ) = (&mut self.grep, &mut self.overlay)
+        {
+            grep.poll(matches);
+            *done = grep.is_done();
+        }
+    }
+
+    fn open_finder_result(&mut self) -> Result<(), AppError> {
+        let path = match (&self.finder, &self.overlay) {
+            (
+                Some(finder),
+                Some(Overlay {
+                    content: OverlayContent::Finder { results, .. },
+                    cursor,
+                    ..
+                }),
+            ) => match results.get(*cursor) {
+                Some(relative) => finder.root().join(relative),
+                None => return Ok(()),
+            },
+            _ => return Ok(()),
+        };
+
+        self.finder = None;
+        self.overlay = None;
+
+        self.go_to_file(&path)
+    }
+
+    /// Goes to the directory a file is in and puts the cursor on it.
+    fn go_to_file(&mut self, path: &Path) -> Result<(), AppError> {
+        if let (Some(directory), Some(name)) = (path.parent(), path.file_name()) {
+            self.set_working_directory(directory.to_path_buf())?;
+
+            if let Some(index) = self.find_entry_with_file_name(&name.to_string_lossy()) {
+                self.cursor = index;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn start_grep(&mut self, pattern: &str) -> Result<(), AppError> {
+        let grep = Grep::start(self.working_directory.clone(), pattern, &self.settings).map_err(
+            |source| AppError::InvalidRegex {
+                pattern: pattern.to_string(),
+                source,
+            },
+        )?;
+
+        self.grep = Some(grep);
+        self.overlay = Some(Overlay::new(OverlayContent::Grep {
+            pattern: pattern.to_string(),
+            matches: Vec::new(),
+            done: false,
+        }));
+
+        Ok(())
+    }
+
+    /// Finds the file and line of the `:grep` result under the overlay's
+    /// cursor.
+    fn selected_grep_match(&self) -> Option<(PathBuf, usize)> {
+        match (&self.grep, &self.overlay) {
+            (
+                Some(grep),
+                Some(Overlay {
+                    content: OverlayContent::Grep { matches, .. },
+                    cursor,
+                    ..
+                }),
+            ) => matches
+                .get(*cursor)
+                .map(|found| (grep.root().join(&found.path), found.line_number)),
+            _ => None,
+        }
+    }
+
+    /// Picks up after a program run with `foreground_command` exits. The
+    /// program might have changed anything, so the listing is read again.
+    pub fn finish_foreground_command(
+        &mut self,
+        command: &ForegroundCommand,
+        result: io::Result<ExitStatus>,
+    ) {
+        let error = match result {
+            Ok(status) if status.success() => {
+                if let Some(shell_command) = &command.shell_command {
+                    self.status_message = Some(format!(
+                        "{} {}",
+                        shell_command,
+                        overlay::describe_exit(status.code())
+                    ));
+                }
+
+                None
+            }
+            Ok(status) => Some(AppError::ProgramFailed {
+                program: command.name().to_string(),
+                code: status.code(),
+            }),
+            Err(source) => Some(AppError::RunProgram {
+                program: command.name().to_string(),
+                source,
+            }),
+        };
+
+        if let Err(error) = self.refresh_working_directory() {
+            self.last_error = Some(error);
+        }
+
+        if error.is_some() {
+            self.last_error = error;
+        }
+    }
+
+    fn resolve_path(&self, path: &str) -> PathBuf {
+        file_ops::resolve_path(&self.working_directory, path)
+    }
+
+    /// Resolves a name given to `:delete`, refusing anything that isn't inside
+    /// the working directory. Otherwise, a slip like `:delete! ..` would take
+    /// the working directory and everything around it along with it.
+    fn deletable_path(&self, name: &str) -> Result<PathBuf, AppError> {
+        let path = self.resolve_path(name);
+        let unsafe_delete = || AppError::UnsafeDelete { path: path.clone() };
+
+        // Only the parent is resolved, since the entry itself might be a
+        // symbolic link, which is deleted instead of what it points to.
+        let (parent, file_name) = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(file_name)) => (parent, file_name),
+            _ => return Err(unsafe_delete()),
+        };
+
+        let resolved = match parent.canonicalize() {
+            Ok(parent) => parent.join(file_name),
+            Err(_) => return Err(unsafe_delete()),
+        };
+        let working_directory = self
+            .working_directory
+            .canonicalize()
+            .map_err(|_| unsafe_delete())?;
+
+        if resolved == working_directory || !resolved.starts_with(&working_directory) {
+            return Err(unsafe_delete());
+        }
+
+        Ok(resolved)
+    }
+
+    fn create_entries(&mut self, paths: &[PathBuf], is_directory: bool) -> Result<(), AppError> {
+        let mut changes = Vec::new();
+
+        let result = paths.iter().try_for_each(|path| {
+            let path = path.clone();
+
+            if is_directory {
+                fs::create_dir(&path).map_err(|source| AppError::CreateDirectory {
+                    path: path.clone(),
+                    source,
+                })?;
+            } else {
+                OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)
+                    .map_err(|source| AppError::CreateFile {
+                        path: path.clone(),
+                        source,
+                    })?;
+            }
+
+            changes.push(Change::Create { path, is_directory });
+            Ok(())
+        });
+
+        self.journal.record(changes);
+        self.refresh_working_directory()?;
+
+        // Move the cursor to highlight the new entry.
+        if let Some(path) = paths.last() {
+            if let Some(index) = self.entries.iter().position(|entry| entry.path == *path) {
+                self.cursor = index;
+            }
+        }
+
+        result
+    }
+
+    /// Fills in `%`, `%s`, and `%d` in a shell command with the entry under
+    /// the cursor, the selected entries, and the working directory. A count
+    /// picks the entries for `%s` like it does for `Yank`.
+    fn expand_placeholders(&self, command: &str, count: u64) -> String {
+        let current = self
+            .entries
+            .get(self.cursor)
+            .filter(|entry| entry.kind != FileEntryKind::Parent)
+            .map(|entry| entry.path.as_path());
+        let selection = self.counted_paths(count);
+
+        shell::expand(
+            command,
+            &Placeholders {
+                current,
+                selection: &selection,
+                directory: &self.working_directory,
+            },
+        )
+    }
+
+    /// Fills in the placeholders in a shell command and leaves it waiting in
+    /// `shell_command`, saying that it's running until it finishes.
+    fn start_shell_command(&mut self, command: &str, count: u64) {
+        self.status_message = Some(format!("Running {}...", command));
+        self.shell_command = Some(PendingCommand {
+            command: command.to_string(),
+            expanded: self.expand_placeholders(command, count),
+        });
+    }
+
+    /// Runs the command waiting in `shell_command` through the shell and
+    /// shows what it printed in an overlay. The command might have changed
+    /// anything, so the listing is read again afterwards.
+    pub fn run_shell_command(&mut self) {
+        let pending = match self.shell_command.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+
+        self.status_message = None;
+
+        if let Err(error) = self.run_pending_command(&pending) {
+            self.last_error = Some(error);
+        }
+    }
+
+    fn run_pending_command(&mut self, pending: &PendingCommand) -> Result<(), AppError> {
+        let command = &pending.command;
+        let result = shell::run_captured(&pending.expanded, &self.working_directory);
+
+        self.refresh_working_directory()?;
+
+        let output = result.map_err(|source| AppError::RunProgram {
+            program: command.to_string(),
+            source,
+        })?;
+        let lines = shell::output_lines(&output);
+        let code = output.status.code();
+
+        if !lines.is_empty() {
+            self.overlay = Some(Overlay::new(OverlayContent::Output {
+                command: command.to_string(),
+                lines,
+                code,
+            }));
+        }
+
+        if !output.status.success() {
+            return Err(AppError::ProgramFailed {
+                program: command.to_string(),
+                code,
+            });
+        }
+
+        self.status_message = Some(format!("{} {}", command, overlay::describe_exit(code)));
+
+        Ok(())
+    }
+
+    /// Goes to a directory typed by the user, relative to the working
+    /// directory.
+    fn go_to_directory(&mut self, path: &str) -> Result<(), AppError> {
+        let path = self.resolve_path(path);
+        let path =
+            fs::canonicalize(&path).map_err(|source| AppError::ReadDirectory { path, source })?;
+
+        self.set_working_directory(path)
+    }
+
+    fn run_command(&mut self, line: &str) -> Result<(), AppError> {
+        let command = match Command::parse(line)? {
+            Some(command) => command,
+            None => return Ok(()),
+        };
+
+        match command {
+            Command::ChangeDirectory(path) => {
+                self.go_to_directory(path.as_ref().map_or("~", String::as_str))?;
+            }
+            Command::MakeDirectory(names) => {
+                let paths: Vec<PathBuf> =
+                    names.iter().map(|name| self.resolve_path(name)).collect();
+                self.create_entries(&paths, true)?;
+            }
+            Command::Touch(names) => {
+                // Like the real `touch`, files that already exist are fine.
+                let paths: Vec<PathBuf> = names
+                    .iter()
+                    .map(|name| self.resolve_path(name))
+                    .filter(|path| !file_ops::path_exists(path))
+                    .collect();
+
+                self.create_entries(&paths, false)?;
+            }
+            Command::Rename {
+                from: None,
+                to,
+                overwrite,
+            } => {
+                self.rename_selected(&to, overwrite)?;
+            }
+            Command::Rename {
+                from: Some(from),
+                to,
+                overwrite,
+            } => {
+                let targets = [(self.resolve_path(&from), self.resolve_path(&to))];
+                self.rename_all(&targets, overwrite)?;
+            }
+            Command::Delete { names, permanent } => {
+                let paths = if names.is_empty() {
+                    self.selected_paths()
+                } else {
+                    names
+                        .iter()
+                        .map(|name| self.deletable_path(name))
+                        .collect::<Result<_, _>>()?
+                };
+
+                if permanent {
+                    self.delete_paths(&paths)?;
+                } else {
+                    self.trash_paths(&paths)?;
+                }
+            }
+            Command::Sort(key) => {
+                self.settings.sort.key = key;
+                self.sort_entries();
+            }
+            Command::Set(options) => {
+                let result = options
+                    .iter()
+                    .try_for_each(|option| self.settings.set(option));
+
+                self.refresh_working_directory()?;
+                result?;
+            }
+            Command::Marks => {
+                self.open_bookmarks()?;
+            }
+            Command::Grep(pattern) => {
+                self.start_grep(&pattern)?;
+            }
+            Command::Shell(command) => {
+                self.start_shell_command(&command, 1);
+            }
+            Command::Terminal(Some(command)) => {
+                let command = self.expand_placeholders(&command, 1);
+                self.foreground_command =
+                    Some(ForegroundCommand::shell(&command, &self.working_directory));
+            }
+            Command::Terminal(None) => {
+                self.foreground_command = Some(ForegroundCommand::interactive_shell(
+                    &self.working_directory,
+                ));
+            }
+            Command::Quit => {
+                self.should_quit = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn find_entry_with_file_name(&self, name: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .enumerate()
+            .find(|(_, entry)| match entry.path.file_name() {
+                Some(file_name) => match file_name.to_str() {
+                    Some(file_name) => file_name == name,
+                    None => false,
+                },
+                None => false,
+            })
+            .map(|(index, _)| index)
+    }
+
+    pub fn process_action(&mut self, action: Action) {
+        self.last_action = Some(action.clone());
+        self.last_error = None;
+        self.status_message = None;
+
+        let change = if AppState::is_repeatable(&action) {
+            Some(action.clone())
+        } else {
+            None
+        };
+
+        match self.process_action_internal(action) {
+            Ok(()) => {
+                if change.is_some() {
+                    self.last_change = change;
+                }
+            }
+            Err(error) => self.last_error = Some(error),
+        }
+    }
+
+    /// Whether an action is a change that `.` can make again. Deleting for
+    /// good can't be undone, so it's only done after asking, and never by a
+    /// stray `.`.
+    fn is_repeatable(action: &Action) -> bool {
+        match action {
+            Action::CreateFile(_)
+            | Action::CreateDirectory(_)
+            | Action::Trash
+            | Action::Paste(_) => true,
+            Action::Rename { pattern, .. } => !pattern.is_empty(),
+            Action::RunCommand(line) => matches!(
+                Command::parse(line),
+                Ok(Some(Command::Shell(_))) | Ok(Some(Command::Terminal(Some(_))))
+            ),
+            _ => false,
+        }
+    }
+
+    /// Makes the last change again against the entries selected now, or
+    /// `count` entries starting at the cursor. There's no reason to paste the
+    /// clipboard once per entry, so pastes are made `count` times instead.
+    fn repeat_last_change(&mut self, count: u64) -> Result<(), AppError> {
+        let change = self.last_change.clone().ok_or(AppError::NothingToRepeat)?;
+
+        match change {
+            Action::Trash => {
+                let paths = self.counted_paths(count);
+                self.trash_paths(&paths)
+            }
+            Action::Rename { pattern, overwrite } => {
+                let targets = self.pattern_targets(&self.counted_paths(count), &pattern);
+                self.rename_all(&targets, overwrite)
+            }
+            Action::Paste(resolution) => (0..count).try_for_each(|_| self.paste(resolution)),
+            Action::RunCommand(line) => match Command::parse(&line)? {
+                Some(Command::Shell(command)) => {
+                    self.start_shell_command(&command, count);
+                    Ok(())
+                }
+                Some(Command::Terminal(Some(command))) => {
+                    let command = self.expand_placeholders(&command, count);
+                    self.foreground_command =
+                        Some(ForegroundCommand::shell(&command, &self.working_directory));
+
+                    Ok(())
+                }
+                _ => Ok(()),
+            },
+            Action::CreateFile(ref name) | Action::CreateDirectory(ref name) => {
+                // The name is usually taken by the entry made last time, so
+                // new entries are numbered instead, like `notes (1).txt`.
+                let path = self.resolve_path(name);
+                let paths = match (path.parent(), path.file_name()) {
+                    (Some(directory), Some(name)) => {
+                        file_ops::unique_paths(directory, name, count as usize)
+                    }
+                    _ => return Ok(()),
+                };
+
+                let is_directory = matches!(change, Action::CreateDirectory(_));
+                self.create_entries(&paths, is_directory)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn process_action_internal(&mut self, action: Action) -> Result<(), AppError> {
+        if let Some(overlay) = &mut self.overlay {
+            match action {
+                Action::Up(count) => overlay.up(count),
+                Action::Down(count) => overlay.down(count),
+                Action::Top => overlay.top(),
+                Action::Bottom => overlay.bottom(),
+                _ => {}
+            }
+        }
+
+        match action {
+            Action::Up(_) | Action::Down(_) | Action::Top | Action::Bottom
+                if self.overlay.is_some() => {}
+            Action::Up(count) => {
+                for _ in 0..count {
+                    if self.cursor > 0 {
+                        self.cursor -= 1;
+                    }
+                }
+            }
+            Action::Down(count) => {
+                for _ in 0..count {
+                    if self.cursor + 1 < self.entries.len() {
+                        self.cursor += 1;
+                    }
+                }
+            }
+            Action::Top => {
+                self.cursor = 0;
+            }
+            Action::Bottom => {
+                self.cursor = self.entries.len().saturating_sub(1);
+            }
+            Action::Activate => {
+                let entry = match self.entries.get(self.cursor) {
+                    Some(entry) => entry,
+                    None => return Ok(()),
+                };
+
+                match entry.kind {
+                    FileEntryKind::Directory | FileEntryKind::Parent => {
+                        self.set_working_directory(entry.path.clone())?;
+                    }
+                    FileEntryKind::File => {
+                        self.open_file(entry.path.clone());
+                    }
+                }
+            }
+            Action::Trash => {
+                self.trash_selected()?;
+            }
+            Action::Delete => {
+                self.delete_selected()?;
+            }
+            Action::OpenTrash => {
+                self.open_trash()?;
+            }
+            Action::RestoreFromTrash => {
+                self.restore_from_trash()?;
+            }
+            Action::ToggleSelection => {
+                if self.visual_anchor.is_some() {
+                    self.mark_visual_range();
+                } else {
+                    self.toggle_selection();
+
+                    if self.cursor + 1 < self.entries.len() {
+                        self.cursor += 1;
+                    }
+                }
+            }
+            Action::InvertSelection => {
+                self.invert_selection();
+            }
+            Action::ClearSelection => {
+                self.clear_selection();
+            }
+            Action::StartVisual if !self.entries.is_empty() => {
+                self.visual_anchor = Some(self.cursor);
+            }
+            Action::Cancel => {
+                self.visual_anchor = None;
+                self.overlay = None;
+                self.finder = None;
+                self.grep = None;
+            }
+            Action::OpenGrepResult => {
+                if let Some((path, _)) = self.selected_grep_match() {
+                    self.grep = None;
+                    self.overlay = None;
+                    self.go_to_file(&path)?;
+                }
+            }
+            Action::EditGrepResult => {
+                if let Some((path, line)) = self.selected_grep_match() {
+                    self.foreground_command = Some(ForegroundCommand::editor(
+                        &[path],
+                        Some(line),
+                        &self.working_directory,
+                    ));
+                }
+            }
+            Action::Edit => {
+                let paths = self.selected_paths();
+
+                if !paths.is_empty() {
+                    self.foreground_command = Some(ForegroundCommand::editor(
+                        &paths,
+                        None,
+                        &self.working_directory,
+                    ));
+                }
+            }
+            Action::View => {
+                // Pagers can't show directories, so those are left out.
+                let paths: Vec<PathBuf> = self
+                    .selected_entries()
+                    .iter()
+                    .filter(|entry| entry.kind == FileEntryKind::File)
+                    .map(|entry| entry.path.clone())
+                    .collect();
+
+                if !paths.is_empty() {
+                    self.foreground_command =
+                        Some(ForegroundCommand::pager(&paths, &self.working_directory));
+                }
+            }
+            Action::OpenFinder => {
+                self.open_finder();
+            }
+            Action::FinderQuery(query) => {
+                self.finder_query = query;
+                self.update_finder_results(true);
+            }
+            Action::OpenFinderResult => {
+                self.open_finder_result()?;
+            }
+            Action::CreateFile(name) => {
+                self.create_entries(&[self.resolve_path(&name)], false)?;
+            }
+            Action::CreateDirectory(name) => {
+                self.create_entries(&[self.resolve_path(&name)], true)?;
+            }
+            Action::Rename { pattern, overwrite } => {
+                self.rename_selected(&pattern, overwrite)?;
+            }
+            Action::Yank(count) => {
+                self.fill_clipboard(ClipboardMode::Copy, count);
+            }
+            Action::Cut(count) => {
+                self.fill_clipboard(ClipboardMode::Move, count);
+            }
+            Action::Paste(resolution) => {
+                self.paste(resolution)?;
+            }
+            Action::Undo => {
+                let result = self.journal.undo(Trash::home().as_ref());
+
+                self.refresh_working_directory()?;
+                result?;
+            }
+            Action::Redo => {
+                let result = self.journal.redo(Trash::home().as_ref());
+
+                self.refresh_working_directory()?;
+                result?;
+            }
+            Action::Repeat(count) => {
+                self.repeat_last_change(count)?;
+            }
+            Action::SetBookmark(letter) => {
+                let path = self.working_directory.clone();
+                self.update_bookmarks(|bookmarks| bookmarks.set(letter, &path))?;
+            }
+            Action::JumpToBookmark(letter) => {
+                self.jump_to_bookmark(letter)?;
+            }
+            Action::DeleteBookmark(letter) => {
+                self.update_bookmarks(|bookmarks| bookmarks.remove(letter))?;
+
+                if self.overlay.is_some() {
+                    self.open_bookmarks()?;
+                }
+            }
+            Action::RunCommand(command) => {
+                self.run_command(&command)?;
+            }
+            Action::HistoryBack => {
+                self.history_back()?;
+            }
+            Action::HistoryForward => {
+                self.history_forward()?;
+            }
+            Action::GoHome => {
+                self.go_to_directory("~")?;
+            }
+            Action::Refresh => {
+                self.refresh_working_directory()?;
+            }
+            Action::SortBy(key) => {
+                self.settings.sort.key = key;
+                self.sort_entries();
+            }
+            Action::ToggleSortReverse => {
+                self.settings.sort.reverse = !self.settings.sort.reverse;
+                self.sort_entries();
+            }
+            Action::ToggleHidden => {
+                self.settings.show_hidden = !self.settings.show_hidden;
+                self.refresh_working_directory()?;
+            }
+            Action::ToggleDirectoriesFirst => {
+                self.settings.sort.directories_first = !self.settings.sort.directories_first;
+                self.sort_entries();
+            }
+            Action::Find(target) => {
+                self.find_target = target;
+
+                self.perform_find();
+            }
+            Action::CycleFindMode => {
+                self.settings.find_mode = self.settings.find_mode.next();
+                self.apply_filter();
+                self.perform_find();
+            }
+            Action::Filter(filter) => {
+                let cursor_path = self
+                    .entries
+                    .get(self.cursor)
+                    .map(|entry| entry.path.clone());
+
+                self.filter = filter;
+                self.apply_filter();
+
+                // If the entry under the cursor was filtered out, jump to the
+                // first match instead of wherever the cursor was clamped to.
+                let still_listed = self
+                    .entries
+                    .get(self.cursor)
+                    .map(|entry| Some(&entry.path) == cursor_path.as_ref())
+                    .unwrap_or(false);
+
+                if !still_listed {
+                    self.cursor = self
+                        .entries
+                        .iter()
+                        .position(|entry| entry.kind != FileEntryKind::Parent)
+                        .unwrap_or(0);
+                }
+            }
+            Action::FindNext => {
+                self.perform_find_next();
+            }
+            Action::FindPrevious => {
+                self.perform_find_previous();
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::file_ops::TempDirectory;
+
+    /// A state browsing `directory` with the cursor on the entry called
+    /// `name`.
+    fn state_on(directory: &Path, name: &str) -> AppState {
+        let mut state = AppState::new(directory.to_path_buf(), Settings::default());
+        state.cursor = state.find_entry_with_file_name(name).unwrap();
+        state
+    }
+
+    #[test]
+    fn case_only_renames() {
+        let directory = TempDirectory::new("case-only-renames");
+        let lower = directory.path().join("foo");
+        let upper = directory.path().join("Foo");
+
+        fs::write(&lower, "lower").unwrap();
+        assert!(!AppState::rename_collides(&lower, &upper));
+
+        fs::write(&upper, "upper").unwrap();
+
+        // On a case-insensitive filesystem, that wrote over `foo` instead.
+        if fs::read_to_string(&lower).unwrap() == "upper" {
+            assert!(!AppState::rename_collides(&lower, &upper));
+            return;
+        }
+
+        assert!(AppState::rename_collides(&lower, &upper));
+
+        let mut state = state_on(directory.path(), "foo");
+        state.process_action(Action::Rename {
+            pattern: "Foo".to_string(),
+            overwrite: false,
+        });
+
+        assert!(state.last_error.is_some());
+        assert_eq!(fs::read_to_string(&lower).unwrap(), "lower");
+        assert_eq!(fs::read_to_string(&upper).unwrap(), "upper");
+    }
+
+    #[test]
+    fn rename_text() {
+        let directory = TempDirectory::new("rename-text");
+
+        for name in &["a", "b", "c"] {
+            fs::write(directory.path().join(name), "").unwrap();
+        }
+
+        let mut state = state_on(directory.path(), "a");
+        state.process_action(Action::Rename {
+            pattern: "{} {n}".to_string(),
+            overwrite: false,
+        });
+        assert!(directory.path().join("{} {n}").exists());
+
+        let mut state = state_on(directory.path(), "b");
+        state.process_action(Action::InvertSelection);
+        state.process_action(Action::Rename {
+            pattern: "{n}-{}".to_string(),
+            overwrite: false,
+        });
+
+        let mut names: Vec<String> = fs::read_dir(directory.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+
+        assert_eq!(names, vec!["1-b", "2-c", "3-{} {n}"]);
+    }
+
+    #[test]
+    fn deleting_named_entries() {
+        let directory = TempDirectory::new("deleting-named-entries");
+        let inner = directory.path().join("inner");
+
+        fs::create_dir(&inner).unwrap();
+        fs::write(inner.join("file"), "").unwrap();
+        fs::write(inner.join("other"), "").unwrap();
+
+        let mut state = AppState::new(inner.clone(), Settings::default());
+
+        for line in &[
+            "delete! ..",
+            "delete! .",
+            "delete! /",
+            "delete! ~",
+            "delete! ../inner",
+        ] {
+            state.process_action(Action::RunCommand(line.to_string()));
+
+            match &state.last_error {
+                Some(AppError::UnsafeDelete { .. }) => {}
+                other => panic!("{} wasn't refused: {:?}", line, other),
+            }
+        }
+
+        assert!(inner.join("file").exists());
+
+        state.process_action(Action::RunCommand(
+            "delete! file ../inner/other".to_string(),
+        ));
+        assert!(state.last_error.is_none());
+        assert!(!inner.join("file").exists());
+        assert!(!inner.join("other").exists());
+        assert!(inner.exists());
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn shell_commands() {
+        let directory = TempDirectory::new("shell-commands");
+        fs::write(directory.path().join("a b"), "").unwrap();
+
+        let mut state = state_on(directory.path(), "a b");
+        state.process_action(Action::RunCommand("!echo %".to_string()));
+
+        // The command only runs once the main loop has shown that it started.
+        assert_eq!(state.status_message.as_deref(), Some("Running echo %..."));
+        assert!(state.overlay.is_none());
+
+        state.run_shell_command();
+        assert!(state.shell_command.is_none());
+
+        match &state.overlay {
+            Some(Overlay {
+                content: OverlayContent::Output { lines, code, .. },
+                ..
+            }) => {
+                let expected = directory.path().join("a b");
+                assert_eq!(lines, &vec![expected.to_string_lossy().to_string()]);
+                assert_eq!(*code, Some(0));
+            }
+            other => panic!("expected the command's output, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn repeatable_actions() {
+        let rename = |pattern: &str| Action::Rename {
+            pattern: pattern.to_string(),
+            overwrite: false,
+        };
+        let command = |line: &str| Action::RunCommand(line.to_string());
+
+        assert!(AppState::is_repeatable(&Action::CreateFile(
+            "a".to_string()
+        )));
+        assert!(AppState::is_repeatable(&rename("{}.bak")));
+        assert!(AppState::is_repeatable(&Action::Trash));
+        assert!(AppState::is_repeatable(&Action::Paste(
+            ConflictResolution::Skip
+        )));
+        assert!(AppState::is_repeatable(&command("!wc -l %")));
+        assert!(AppState::is_repeatable(&command("sh vim %")));
+
+        assert!(!AppState::is_repeatable(&Action::Delete));
+        assert!(!AppState::is_repeatable(&rename("")));
+        assert!(!AppState::is_repeatable(&command("sh")));
+        assert!(!AppState::is_repeatable(&command("delete! a")));
+        assert!(!AppState::is_repeatable(&command("cd ..")));
+        assert!(!AppState::is_repeatable(&Action::Down(1)));
+        assert!(!AppState::is_repeatable(&Action::Yank(1)));
+        assert!(!AppState::is_repeatable(&Action::Repeat(1)));
+    }
+
+    #[test]
+    fn repeating_changes() {
+        let directory = TempDirectory::new("repeating-changes");
+
+        for name in &["a", "b", "c", "d", "e"] {
+            fs::write(directory.path().join(name), "").unwrap();
+        }
+
+        let mut state = state_on(directory.path(), "a");
+        state.process_action(Action::Repeat(1));
+
+        match &state.last_error {
+            Some(AppError::NothingToRepeat) => {}
+            other => panic!("expected nothing to repeat, got {:?}", other),
+        }
+
+        let rename = Action::Rename {
+            pattern: "{}.bak".to_string(),
+            overwrite: false,
+        };
+        state.process_action(Action::ToggleSelection);
+        state.process_action(Action::ToggleSelection);
+        state.process_action(rename.clone());
+        assert_eq!(state.last_change, Some(rename.clone()));
+
+        // Neither moving around nor changes that failed replace it.
+        state.process_action(Action::Bottom);
+        state.process_action(Action::Find("c".to_string()));
+        state.process_action(Action::Yank(1));
+        state.process_action(Action::ToggleHidden);
+        state.process_action(Action::CreateFile("c".to_string()));
+        assert!(state.last_error.is_some());
+        assert_eq!(state.last_change, Some(rename.clone()));
+
+        // A single entry is renamed using the pattern too, and a count takes
+        // that many entries from the cursor.
+        state.cursor = state.find_entry_with_file_name("c").unwrap();
+        state.process_action(Action::Repeat(1));
+        state.cursor = state.find_entry_with_file_name("d").unwrap();
+        state.process_action(Action::Repeat(2));
+        assert!(state.last_error.is_none());
+
+        let mut names: Vec<String> = fs::read_dir(directory.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+
+        assert_eq!(names, vec!["a.bak", "b.bak", "c.bak", "d.bak", "e.bak"]);
+
+        // Deleting for good isn't repeatable, so `.` goes on renaming.
+        state.cursor = state.find_entry_with_file_name("e.bak").unwrap();
+        state.process_action(Action::Delete);
+        assert!(!directory.path().join("e.bak").exists());
+        assert_eq!(state.last_change, Some(rename));
+
+        state.cursor = state.find_entry_with_file_name("a.bak").unwrap();
+        state.process_action(Action::Repeat(1));
+        assert!(directory.path().join("a.bak.bak").exists());
+        assert!(directory.path().join("b.bak").exists());
+
+        // Creates are numbered, since the name was taken by the first one.
+        state.process_action(Action::CreateDirectory("new".to_string()));
+        state.process_action(Action::Repeat(2));
+        assert!(state.last_error.is_none());
+        assert!(directory.path().join("new (1)").is_dir());
+        assert!(directory.path().join("new (2)").is_dir());
+        assert_eq!(state.entries[state.cursor].display, "new (2)/");
+
+        // They're undone together.
+        state.process_action(Action::Undo);
+        assert!(directory.path().join("new").is_dir());
+        assert!(!directory.path().join("new (1)").exists());
+    }
+
+    #[test]
+    fn directory_history() {
+        let directory = TempDirectory::new("directory-history");
+        let first = directory.path().join("first");
+        let second = directory.path().join("second");
+        let third = directory.path().join("third");
+
+        for path in &[&first, &second, &third] {
+            fs::create_dir(path).unwrap();
+        }
+
+        fs::write(first.join("b"), "").unwrap();
+        fs::write(first.join("c"), "").unwrap();
+
+        let mut state = state_on(&first, "c");
+        state.set_working_directory(second.clone()).unwrap();
+        state.set_working_directory(third.clone()).unwrap();
+        assert_eq!(state.history_back, vec![first.clone(), second.clone()]);
+
+        // An entry added while we were away moves `c` down, but the cursor
+        // follows it by name.
+        fs::write(first.join("a"), "").unwrap();
+
+        state.process_action(Action::HistoryBack);
+        state.process_action(Action::HistoryBack);
+        assert_eq!(state.working_directory, first);
+        assert_eq!(state.entries[state.cursor].path, first.join("c"));
+        assert!(state.history_back.is_empty());
+        assert_eq!(state.history_forward, vec![third.clone(), second.clone()]);
+
+        state.process_action(Action::HistoryForward);
+        assert_eq!(state.working_directory, second);
+        assert_eq!(state.history_back, vec![first.clone()]);
+        assert_eq!(state.history_forward, vec![third.clone()]);
+
+        // Going somewhere new forgets where we could have gone forward to.
+        state
+            .set_working_directory(directory.path().to_path_buf())
+            .unwrap();
+        assert_eq!(state.history_back, vec![first.clone(), second.clone()]);
+        assert!(state.history_forward.is_empty());
+    }
+
+    #[test]
+    fn filtering() {
+        let directory = TempDirectory::new("filtering");
+        let inner = directory.path().join("inner");
+
+        fs::create_dir(&inner).unwrap();
+
+        for name in &["apple", "apricot", "banana"] {
+            fs::write(directory.path().join(name), "").unwrap();
+        }
+
+        let names = |state: &AppState| -> Vec<String> {
+            state
+                .entries
+                .iter()
+                .map(|entry| entry.display.clone())
+                .collect()
+        };
+
+        let mut state = state_on(directory.path(), "apricot");
+        state.process_action(Action::Filter("ap".to_string()));
+        assert_eq!(names(&state), vec!["..", "apple", "apricot"]);
+        assert_eq!(state.entries[state.cursor].display, "apricot");
+
+        // The entry under the cursor was filtered out, so the cursor moves to
+        // the first match, skipping the parent directory.
+        state.process_action(Action::Filter("app".to_string()));
+        assert_eq!(names(&state), vec!["..", "apple"]);
+        assert_eq!(state.entries[state.cursor].display, "apple");
+
+        state.process_action(Action::Filter("cherry".to_string()));
+        assert_eq!(names(&state), vec![".."]);
+        assert_eq!(state.cursor, 0);
+
+        // Escape clears the filter this way.
+        state.process_action(Action::Filter("ap".to_string()));
+        state.cursor = 2;
+        state.process_action(Action::Filter(String::new()));
+        assert_eq!(state.entries.len(), 5);
+        assert_eq!(state.entries[state.cursor].display, "apricot");
+
+        state.process_action(Action::Filter("ban".to_string()));
+        state.set_working_directory(inner).unwrap();
+        assert!(state.filter.is_empty());
+
+        state.process_action(Action::HistoryBack);
+        assert_eq!(state.entries.len(), 5);
     }
 }
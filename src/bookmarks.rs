@@ -0,0 +1,86 @@
+//! Bookmarks are stored in a plain text file with one bookmark per line, the
+//! letter and the directory separated by a tab. The file is read again every
+//! time it's used so that changes synced in from elsewhere show up right away.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug)]
+pub struct Bookmarks {
+    file_path: PathBuf,
+}
+
+impl Bookmarks {
+    /// Locates the bookmarks file in the user's data directory, returning
+    /// `None` if there's no data directory.
+    pub fn default_location() -> Option<Bookmarks> {
+        let file_path = dirs::data_dir()?.join("msb").join("bookmarks");
+
+        Some(Bookmarks { file_path })
+    }
+
+    pub fn file_path(&self) -> &Path {
+        &self.file_path
+    }
+
+    pub fn load(&self) -> io::Result<BTreeMap<char, PathBuf>> {
+        let contents = match fs::read_to_string(&self.file_path) {
+            Ok(contents) => contents,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error),
+        };
+
+        let mut marks = BTreeMap::new();
+
+        for line in contents.lines() {
+            let mut parts = line.splitn(2, '\t');
+
+            if let (Some(letter), Some(path)) = (parts.next(), parts.next()) {
+                let mut chars = letter.chars();
+
+                if let (Some(letter), None) = (chars.next(), chars.next()) {
+                    marks.insert(letter, PathBuf::from(path));
+                }
+            }
+        }
+
+        Ok(marks)
+    }
+
+    fn save(&self, marks: &BTreeMap<char, PathBuf>) -> io::Result<()> {
+        if let Some(parent) = self.file_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut contents = String::new();
+
+        for (letter, path) in marks {
+            contents.push_str(&format!("{}\t{}\n", letter, path.display()));
+        }
+
+        // Write to a temporary file first so that a sync tool never sees a
+        // half-written bookmarks file.
+        let temp_path = self.file_path.with_extension("tmp");
+        fs::write(&temp_path, contents)?;
+        fs::rename(&temp_path, &self.file_path)
+    }
+
+    pub fn get(&self, letter: char) -> io::Result<Option<PathBuf>> {
+        Ok(self.load()?.remove(&letter))
+    }
+
+    pub fn set(&self, letter: char, path: &Path) -> io::Result<()> {
+        let mut marks = self.load()?;
+        marks.insert(letter, path.to_path_buf());
+        self.save(&marks)
+    }
+
+    pub fn remove(&self, letter: char) -> io::Result<()> {
+        let mut marks = self.load()?;
+        marks.remove(&letter);
+        self.save(&marks)
+    }
+}
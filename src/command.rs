@@ -0,0 +1,318 @@
+//! The language typed into the command prompt after `:`, like
+//! `:cd ~/projects` or `:rename "old name" "new name"`.
+
+use std::mem;
+
+use crate::{error::AppError, sort::SortKey};
+
+/// A command from the command prompt, with its arguments checked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Goes to a directory, or the home directory if none is given.
+    ChangeDirectory(Option<String>),
+    MakeDirectory(Vec<String>),
+    Touch(Vec<String>),
+
+    /// Renames one entry to `to`, or the selected entries if `from` isn't
+    /// given. `to` can be a pattern like the one taken by `R`. With
+    /// `overwrite`, entries already using the new name are replaced.
+    Rename {
+        from: Option<String>,
+        to: String,
+        overwrite: bool,
+    },
+
+    /// Moves the named entries to the trash, or the selected entries if none
+    /// are named. With `permanent`, they're deleted for good instead.
+    Delete {
+        names: Vec<String>,
+        permanent: bool,
+    },
+
+    Sort(SortKey),
+    Set(Vec<String>),
+    Marks,
+    Grep(String),
+
+    /// Runs a shell command and shows what it printed, from `:!command`.
+    Shell(String),
+
+    /// Hands the terminal over to a shell command, or to an interactive shell
+    /// if there's no command.
+    Terminal(Option<String>),
+
+    Quit,
+}
+
+impl Command {
+    /// Every command name, for completing them.
+    pub const NAMES: [&'static str; 13] = [
+        "cd", "delete", "grep", "marks", "mkdir", "q", "quit", "rename", "set", "sh", "shell",
+        "sort", "touch",
+    ];
+
+    /// Parses a line from the command prompt. A blank line isn't a command,
+    /// so it parses to `None`.
+    pub fn parse(line: &str) -> Result<Option<Command>, AppError> {
+        // Shell commands are handed to the shell as they are, quotes and all.
+        let trimmed = line.trim();
+
+        if let Some(command) = trimmed.strip_prefix('!') {
+            let command = command.trim();
+
+            if command.is_empty() {
+                return Err(AppError::Usage(":!<command>"));
+            }
+
+            return Ok(Some(Command::Shell(command.to_string())));
+        }
+
+        let mut raw_words = trimmed.splitn(2, char::is_whitespace);
+
+        if let Some("sh") | Some("shell") = raw_words.next() {
+            let command = raw_words.next().map(str::trim).unwrap_or("");
+
+            if command.is_empty() {
+                return Ok(Some(Command::Terminal(None)));
+            }
+
+            return Ok(Some(Command::Terminal(Some(command.to_string()))));
+        }
+
+        let mut words = split_words(line)?.into_iter();
+
+        let name = match words.next() {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+
+        let arguments: Vec<String> = words.collect();
+
+        let command = match name.as_str() {
+            "cd" => match arguments.len() {
+                0 | 1 => Command::ChangeDirectory(arguments.into_iter().next()),
+                _ => return Err(AppError::Usage(":cd [directory]")),
+            },
+            "mkdir" => {
+                if arguments.is_empty() {
+                    return Err(AppError::Usage(":mkdir <name>..."));
+                }
+
+                Command::MakeDirectory(arguments)
+            }
+            "touch" => {
+                if arguments.is_empty() {
+                    return Err(AppError::Usage(":touch <name>..."));
+                }
+
+                Command::Touch(arguments)
+            }
+            "rename" | "rename!" => {
+                let overwrite = name.ends_with('!');
+                let mut arguments = arguments.into_iter();
+
+                match (arguments.next(), arguments.next(), arguments.next()) {
+                    (Some(to), None, None) => Command::Rename {
+                        from: None,
+                        to,
+                        overwrite,
+                    },
+                    (Some(from), Some(to), None) => Command::Rename {
+                        from: Some(from),
+                        to,
+                        overwrite,
+                    },
+                    _ => return Err(AppError::Usage(":rename [name] <new name>")),
+                }
+            }
+            "delete" | "delete!" => Command::Delete {
+                permanent: name.ends_with('!'),
+                names: arguments,
+            },
+            "sort" => {
+                if arguments.len() != 1 {
+                    return Err(AppError::Usage(":sort <order>"));
+                }
+
+                let key = &arguments[0];
+                let key =
+                    SortKey::from_name(key).ok_or_else(|| AppError::UnknownSortKey(key.clone()))?;
+
+                Command::Sort(key)
+            }
+            "set" => {
+                if arguments.is_empty() {
+                    return Err(AppError::Usage(":set <option>..."));
+                }
+
+                Command::Set(arguments)
+            }
+            "marks" => Command::Marks,
+            "grep" => {
+                if arguments.is_empty() {
+                    return Err(AppError::Usage(":grep <pattern>"));
+                }
+
+                // Spaces between words are kept, so quoting the pattern is
+                // only needed to keep several spaces in a row.
+                Command::Grep(arguments.join(" "))
+            }
+            "q" | "quit" => Command::Quit,
+            _ => return Err(AppError::UnknownCommand(name)),
+        };
+
+        Ok(Some(command))
+    }
+}
+
+/// The last word of a line that's still being typed, for completing it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialWord {
+    /// Where the word starts in the line, in characters. A line ending in
+    /// whitespace has an empty word starting at its very end.
+    pub start: usize,
+
+    /// The quote the word is inside of, if it hasn't been closed yet.
+    pub quote: Option<char>,
+
+    /// The word with its quotes and escapes taken out.
+    pub text: String,
+}
+
+/// Splits a line into words at whitespace, a lot like a shell does. Single
+/// quotes keep everything inside them as-is, double quotes keep whitespace,
+/// and a backslash escapes whitespace, quotes, or another backslash.
+///
+/// Any other backslash is kept, so that regexes like `\d+` can be typed
+/// without doubling every backslash.
+fn split_words(line: &str) -> Result<Vec<String>, AppError> {
+    let (mut words, last) = split_partial(line);
+
+    if let Some(quote) = last.quote {
+        return Err(AppError::UnclosedQuote(quote));
+    }
+
+    if last.start < line.chars().count() {
+        words.push(last.text);
+    }
+
+    Ok(words)
+}
+
+/// Splits a line like `split_words`, but keeps the last word apart, since it
+/// might not be finished yet.
+pub fn split_partial(line: &str) -> (Vec<String>, PartialWord) {
+    let mut words = Vec::new();
+    let mut word = PartialWord {
+        start: 0,
+        quote: None,
+        text: String::new(),
+    };
+    let mut in_word = false;
+    let mut chars = line.chars().enumerate().peekable();
+
+    while let Some((index, char)) = chars.next() {
+        if !in_word {
+            word.start = index;
+        }
+
+        match (word.quote, char) {
+            (Some(quote), _) if char == quote => {
+                word.quote = None;
+            }
+            (Some('"'), '\\') | (None, '\\') => {
+                in_word = true;
+
+                let escapes = |next: char| match word.quote {
+                    Some(_) => next == '"' || next == '\\',
+                    None => next.is_whitespace() || "'\"\\".contains(next),
+                };
+
+                match chars.peek() {
+                    Some(&(_, next)) if escapes(next) => {
+                        word.text.push(next);
+                        chars.next();
+                    }
+                    _ => word.text.push('\\'),
+                }
+            }
+            (Some(_), _) => word.text.push(char),
+            (None, '\'') | (None, '"') => {
+                in_word = true;
+                word.quote = Some(char);
+            }
+            (None, _) if char.is_whitespace() => {
+                if in_word {
+                    words.push(mem::take(&mut word.text));
+                    in_word = false;
+                }
+            }
+            (None, _) => {
+                in_word = true;
+                word.text.push(char);
+            }
+        }
+    }
+
+    if !in_word {
+        word.start = line.chars().count();
+    }
+
+    (words, word)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn words(line: &str) -> Vec<String> {
+        split_words(line).unwrap()
+    }
+
+    #[test]
+    fn word_splitting() {
+        assert_eq!(words("  cd   foo "), vec!["cd", "foo"]);
+        assert_eq!(
+            words("touch 'a b' \"c d\" e\\ f"),
+            vec!["touch", "a b", "c d", "e f"]
+        );
+        assert_eq!(
+            words("grep \\d+ '\\n' \"\\\"\""),
+            vec!["grep", "\\d+", "\\n", "\""]
+        );
+        assert_eq!(words("touch ''"), vec!["touch", ""]);
+        assert!(split_words("cd 'foo").is_err());
+    }
+
+    #[test]
+    fn command_parsing() {
+        assert_eq!(Command::parse("   ").unwrap(), None);
+        assert_eq!(
+            Command::parse("rename 'old name' new").unwrap(),
+            Some(Command::Rename {
+                from: Some("old name".to_string()),
+                to: "new".to_string(),
+                overwrite: false,
+            })
+        );
+        assert_eq!(
+            Command::parse("delete! a").unwrap(),
+            Some(Command::Delete {
+                names: vec!["a".to_string()],
+                permanent: true,
+            })
+        );
+        assert_eq!(
+            Command::parse("grep fn  main").unwrap(),
+            Some(Command::Grep("fn main".to_string()))
+        );
+        assert_eq!(
+            Command::parse(" !echo 'a  b' ").unwrap(),
+            Some(Command::Shell("echo 'a  b'".to_string()))
+        );
+        assert_eq!(Command::parse("sh").unwrap(), Some(Command::Terminal(None)));
+        assert!(Command::parse("rename").is_err());
+        assert!(Command::parse("sort sideways").is_err());
+        assert!(Command::parse("frobnicate").is_err());
+    }
+}
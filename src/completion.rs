@@ -0,0 +1,279 @@
+//! Tab completion for the text prompts: command names and their arguments
+//! after `:`, and paths wherever names are typed.
+
+use std::{fs, path::Path};
+
+use crate::{
+    command::{self, Command},
+    file_ops,
+    matcher::MatchMode,
+    settings::Settings,
+    sort::SortKey,
+};
+
+/// One thing the word being completed could turn into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    /// The whole word, like `src/main.rs`.
+    pub value: String,
+
+    /// What to show in the list of candidates, like `main.rs`.
+    pub label: String,
+}
+
+impl Candidate {
+    fn new(value: &str) -> Candidate {
+        Candidate {
+            value: value.to_string(),
+            label: value.to_string(),
+        }
+    }
+
+    /// Directories can be completed further, so typing carries on inside
+    /// them instead of moving on to the next word.
+    fn is_finished(&self) -> bool {
+        !self.value.ends_with('/') && !self.value.ends_with('=')
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+    /// Where the word being completed starts in the text, in characters.
+    pub start: usize,
+
+    /// Whether the text is a command, where words need quoting or escaping.
+    quoted: bool,
+
+    /// The quote the word is inside of, if it hasn't been closed yet.
+    quote: Option<char>,
+
+    pub candidates: Vec<Candidate>,
+}
+
+impl Completion {
+    /// The text to replace the word with: the candidate if there's only one,
+    /// or otherwise as much as all the candidates have in common.
+    pub fn replacement(&self) -> Option<String> {
+        let first = self.candidates.first()?;
+
+        let mut text = if self.candidates.len() == 1 {
+            first.value.clone()
+        } else {
+            common_prefix(&self.candidates)
+        };
+
+        if !self.quoted {
+            return Some(text);
+        }
+
+        text = quote(&text, self.quote);
+
+        // A finished word in a command is ready for the next one.
+        if self.candidates.len() == 1 && first.is_finished() {
+            if let Some(quote) = self.quote {
+                text.push(quote);
+            }
+
+            text.push(' ');
+        }
+
+        Some(text)
+    }
+}
+
+/// Completes the last word of a command typed after `:`.
+pub fn complete_command(line: &str, directory: &Path) -> Completion {
+    let (words, word) = command::split_partial(line);
+
+    let candidates = match words.first().map(String::as_str) {
+        None => matching(&Command::NAMES, &word.text),
+        Some("cd") | Some("mkdir") => complete_path(&word.text, directory, true),
+        Some("touch") | Some("rename") | Some("rename!") | Some("delete") | Some("delete!") => {
+            complete_path(&word.text, directory, false)
+        }
+        Some("sort") if words.len() == 1 => {
+            let names: Vec<&str> = SortKey::ALL.iter().map(|key| key.name()).collect();
+            matching(&names, &word.text)
+        }
+        Some("set") => complete_option(&word.text),
+        Some(_) => Vec::new(),
+    };
+
+    Completion {
+        start: word.start,
+        quoted: true,
+        quote: word.quote,
+        candidates,
+    }
+}
+
+/// Completes a name typed into a prompt, like the new file prompt, where the
+/// whole text is one path.
+pub fn complete_name(text: &str, directory: &Path, directories_only: bool) -> Completion {
+    Completion {
+        start: 0,
+        quoted: false,
+        quote: None,
+        candidates: complete_path(text, directory, directories_only),
+    }
+}
+
+fn matching(names: &[&str], prefix: &str) -> Vec<Candidate> {
+    names
+        .iter()
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| Candidate::new(name))
+        .collect()
+}
+
+/// Completes an option given to `:set`, including `no` in front of it and
+/// the value of `find=`.
+fn complete_option(word: &str) -> Vec<Candidate> {
+    let mode_prefix = "find=";
+
+    if word.starts_with(mode_prefix) {
+        let names: Vec<String> = MatchMode::ALL
+            .iter()
+            .map(|mode| format!("{}{}", mode_prefix, mode.name()))
+            .collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+
+        return matching(&names, word);
+    }
+
+    let mut names: Vec<String> = Settings::OPTION_NAMES
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+
+    // Options that are only turned on and off can be negated.
+    if word.starts_with("no") {
+        names.extend(
+            Settings::OPTION_NAMES
+                .iter()
+                .filter(|name| !name.ends_with('='))
+                .map(|name| format!("no{}", name)),
+        );
+    }
+
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+    matching(&names, word)
+}
+
+/// Finds the entries that a partly typed path could be completed to. Like in
+/// a shell, entries starting with a `.` only show up once a `.` is typed.
+fn complete_path(word: &str, directory: &Path, directories_only: bool) -> Vec<Candidate> {
+    if word == "~" {
+        return vec![Candidate::new("~/")];
+    }
+
+    let (parent, prefix) = match word.rfind('/') {
+        Some(index) => word.split_at(index + 1),
+        None => ("", word),
+    };
+
+    let entries = match fs::read_dir(file_ops::resolve_path(directory, parent)) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut candidates: Vec<Candidate> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let mut name = entry.file_name().to_string_lossy().to_string();
+
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+
+            if entry.path().is_dir() {
+                name.push('/');
+            } else if directories_only {
+                return None;
+            }
+
+            Some(Candidate {
+                value: format!("{}{}", parent, name),
+                label: name,
+            })
+        })
+        .collect();
+
+    candidates.sort_by(|a, b| a.value.cmp(&b.value));
+    candidates
+}
+
+fn common_prefix(candidates: &[Candidate]) -> String {
+    let mut prefix: Vec<char> = match candidates.first() {
+        Some(first) => first.value.chars().collect(),
+        None => return String::new(),
+    };
+
+    for candidate in &candidates[1..] {
+        let shared = prefix
+            .iter()
+            .zip(candidate.value.chars())
+            .take_while(|(a, b)| **a == *b)
+            .count();
+
+        prefix.truncate(shared);
+    }
+
+    prefix.into_iter().collect()
+}
+
+/// Writes a word so that the command parser reads it back the same way:
+/// inside the quote it was started with, or with special characters escaped.
+fn quote(word: &str, quote: Option<char>) -> String {
+    let mut quoted = String::new();
+
+    if let Some(quote) = quote {
+        quoted.push(quote);
+    }
+
+    for char in word.chars() {
+        let escaped = match quote {
+            Some('"') => char == '"' || char == '\\',
+            Some(_) => false,
+            None => char.is_whitespace() || "'\"\\".contains(char),
+        };
+
+        if escaped {
+            quoted.push('\\');
+        }
+
+        quoted.push(char);
+    }
+
+    quoted
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn replace(line: &str) -> Option<String> {
+        complete_command(line, Path::new("/nonexistent")).replacement()
+    }
+
+    #[test]
+    fn command_completion() {
+        assert_eq!(replace("mk"), Some("mkdir ".to_string()));
+        assert_eq!(replace("m"), Some("m".to_string()));
+        assert_eq!(replace("sort nat"), Some("natural ".to_string()));
+        assert_eq!(replace("set nosi"), Some("nosize ".to_string()));
+        assert_eq!(replace("set fi"), Some("find=".to_string()));
+        assert_eq!(replace("set find=fu"), Some("find=fuzzy ".to_string()));
+        assert_eq!(replace("grep x"), None);
+
+        let completion = complete_command("set h", Path::new("/nonexistent"));
+        assert_eq!(completion.start, 4);
+    }
+
+    #[test]
+    fn word_quoting() {
+        assert_eq!(quote("a b's", None), "a\\ b\\'s");
+        assert_eq!(quote("a \"b\"", Some('"')), "\"a \\\"b\\\"");
+        assert_eq!(quote("a b", Some('\'')), "'a b");
+    }
+}
@@ -0,0 +1,511 @@
+//! The config file, read once at startup from `msb/config.toml` in the user's
+//! config directory, or from the file given with `--config`.
+//!
+//! ```toml
+//! [options]
+//! hidden = true
+//! sort = "natural"
+//!
+//! [colors]
+//! cursor = { fg = "black", bg = "green" }
+//!
+//! [keys]
+//! leader = "<space>"
+//!
+//! [keys.normal]
+//! J = "down"
+//! "<leader>f" = "finder"
+//! ```
+
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use toml::Value;
+
+use crate::{
+    input_state::InputMode,
+    keymap::{self, Binding, Keymap},
+    matcher::MatchMode,
+    settings::Settings,
+    sort::SortKey,
+    terminal_context::{Color, Key},
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// The options the listing starts out with, which can still be changed
+    /// with `:set` and `:sort`.
+    pub settings: Settings,
+    pub theme: Theme,
+    pub keymap: Keymap,
+}
+
+/// A foreground and background color to draw something with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorPair {
+    pub foreground: Color,
+    pub background: Color,
+}
+
+impl ColorPair {
+    fn new(foreground: Color, background: Color) -> ColorPair {
+        ColorPair {
+            foreground,
+            background,
+        }
+    }
+}
+
+/// The colors that the interface is drawn with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    /// The bar at the top showing the working directory.
+    pub header: ColorPair,
+
+    /// The entry under the cursor, in the listing and in overlays.
+    pub cursor: ColorPair,
+
+    /// Marked entries, and entries in the visual range.
+    pub selected: ColorPair,
+
+    /// Entries, or the characters in them, matching the find prompt.
+    pub find_match: ColorPair,
+
+    /// The status bar at the bottom.
+    pub status: ColorPair,
+
+    /// The status bar when it's showing an error.
+    pub error: ColorPair,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme {
+            header: ColorPair::new(Color::Black, Color::White),
+            cursor: ColorPair::new(Color::Black, Color::White),
+            selected: ColorPair::new(Color::Black, Color::Cyan),
+            find_match: ColorPair::new(Color::Black, Color::Yellow),
+            status: ColorPair::new(Color::Black, Color::White),
+            error: ColorPair::new(Color::White, Color::Red),
+        }
+    }
+}
+
+impl Theme {
+    fn pair(&mut self, name: &str) -> Option<&mut ColorPair> {
+        match name {
+            "header" => Some(&mut self.header),
+            "cursor" => Some(&mut self.cursor),
+            "selected" => Some(&mut self.selected),
+            "match" => Some(&mut self.find_match),
+            "status" => Some(&mut self.status),
+            "error" => Some(&mut self.error),
+            _ => None,
+        }
+    }
+}
+
+const COLOR_NAMES: [(&str, Color); 9] = [
+    ("default", Color::Reset),
+    ("black", Color::Black),
+    ("red", Color::Red),
+    ("green", Color::Green),
+    ("yellow", Color::Yellow),
+    ("blue", Color::Blue),
+    ("magenta", Color::Magenta),
+    ("cyan", Color::Cyan),
+    ("white", Color::White),
+];
+
+/// A problem with the config file, reported before the interface starts.
+#[derive(Debug)]
+pub enum ConfigError {
+    Read {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    /// The file is valid TOML, but something in it doesn't make sense, like
+    /// an option that doesn't exist. `key` is where, like `options.sort`.
+    Invalid {
+        path: PathBuf,
+        key: String,
+        problem: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, output: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Read { path, source } => write!(
+                output,
+                "Could not read config file {}: {}",
+                path.display(),
+                source
+            ),
+            ConfigError::Parse { path, source } => write!(
+                output,
+                "Could not parse config file {}: {}",
+                path.display(),
+                source
+            ),
+            ConfigError::Invalid { path, key, problem } => write!(
+                output,
+                "Error in config file {}: {}: {}",
+                path.display(),
+                key,
+                problem
+            ),
+        }
+    }
+}
+
+/// Where the config file is looked for when `--config` isn't given.
+pub fn default_path() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("msb").join("config.toml"))
+}
+
+impl Config {
+    /// Reads the config file at `path`, or at the default path if there is
+    /// none. Not having a config file at the default path is fine, but a file
+    /// given with `--config` has to exist.
+    pub fn load(path: Option<&Path>) -> Result<Config, ConfigError> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound && !required => {
+                return Ok(Config::default());
+            }
+            Err(source) => return Err(ConfigError::Read { path, source }),
+        };
+
+        Config::parse(&text, &path)
+    }
+
+    /// Reads a config file's contents. `path` is only used to say where
+    /// errors are.
+    pub fn parse(text: &str, path: &Path) -> Result<Config, ConfigError> {
+        let invalid = |key: &str, problem: String| ConfigError::Invalid {
+            path: path.to_path_buf(),
+            key: key.to_string(),
+            problem,
+        };
+
+        let value = text.parse::<Value>().map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        let mut config = Config::default();
+
+        // The leader has to be known before any sequences using it are read.
+        if let Some(keys) = value.get("keys") {
+            set_key_options(&mut config.keymap, keys)
+                .map_err(|(name, problem)| invalid(&format!("keys.{}", name), problem))?;
+        }
+
+        for (section, value) in table(&value).map_err(|problem| invalid("", problem))? {
+            let entries = table(value).map_err(|problem| invalid(section, problem))?;
+
+            for (name, value) in entries {
+                let key = format!("{}.{}", section, name);
+
+                let result = match section.as_str() {
+                    "options" => set_option(&mut config.settings, name, value),
+                    "colors" => set_colors(&mut config.theme, name, value),
+                    "keys" if name == "leader" || name == "timeout" => Ok(()),
+                    "keys" => {
+                        let mode = keymap_mode(name).map_err(|problem| invalid(&key, problem))?;
+                        let bindings = table(value).map_err(|problem| invalid(&key, problem))?;
+
+                        // Errors point at the exact key they're about, like
+                        // `keys.normal.J`, rather than the whole table.
+                        for (key_text, value) in bindings {
+                            bind_key(&mut config.keymap, (name, mode), key_text, value).map_err(
+                                |problem| invalid(&format!("{}.{}", key, key_text), problem),
+                            )?;
+                        }
+
+                        Ok(())
+                    }
+                    _ => return Err(invalid(section, "unknown section".to_string())),
+                };
+
+                result.map_err(|problem| invalid(&key, problem))?;
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn table(value: &Value) -> Result<&toml::value::Table, String> {
+    value.as_table().ok_or_else(|| expected("a table", value))
+}
+
+fn string(value: &Value) -> Result<&str, String> {
+    value.as_str().ok_or_else(|| expected("a string", value))
+}
+
+fn expected(what: &str, value: &Value) -> String {
+    format!("expected {}, found {}", what, value.type_str())
+}
+
+/// Sets an option the way `:set` would. Options that are turned on and off
+/// take `true` or `false`, and the others take a string, except for `ignore`,
+/// which can also take a list of globs.
+fn set_option(settings: &mut Settings, name: &str, value: &Value) -> Result<(), String> {
+    match name {
+        "sort" => {
+            let key = string(value)?;
+
+            settings.sort.key = SortKey::from_name(key).ok_or_else(|| {
+                let names: Vec<&str> = SortKey::ALL.iter().map(|key| key.name()).collect();
+                format!(
+                    "unknown sort order '{}', expected one of {}",
+                    key,
+                    names.join(", ")
+                )
+            })?;
+
+            Ok(())
+        }
+        "find" => {
+            let mode = string(value)?;
+
+            settings.find_mode = MatchMode::from_name(mode).ok_or_else(|| {
+                let names: Vec<&str> = MatchMode::ALL.iter().map(|mode| mode.name()).collect();
+                format!(
+                    "unknown find mode '{}', expected one of {}",
+                    mode,
+                    names.join(", ")
+                )
+            })?;
+
+            Ok(())
+        }
+        "ignore" => {
+            let patterns = match value.as_array() {
+                Some(patterns) => patterns
+                    .iter()
+                    .map(string)
+                    .collect::<Result<Vec<&str>, String>>()?
+                    .join(","),
+                None => string(value)?.to_string(),
+            };
+
+            settings
+                .set(&format!("ignore={}", patterns))
+                .map_err(|error| error.to_string())
+        }
+        _ if Settings::OPTION_NAMES.contains(&name) => {
+            let on = value
+                .as_bool()
+                .ok_or_else(|| expected("true or false", value))?;
+            let option = if on {
+                name.to_string()
+            } else {
+                format!("no{}", name)
+            };
+
+            settings.set(&option).map_err(|error| error.to_string())
+        }
+        _ => Err("unknown option".to_string()),
+    }
+}
+
+/// Sets the colors for one part of the interface from a table like
+/// `{ fg = "black", bg = "white" }`.
+fn set_colors(theme: &mut Theme, name: &str, value: &Value) -> Result<(), String> {
+    let pair = theme
+        .pair(name)
+        .ok_or_else(|| "unknown part of the interface".to_string())?;
+
+    for (which, value) in table(value)? {
+        let color_name = string(value)?;
+        let color = COLOR_NAMES
+            .iter()
+            .find(|(name, _)| *name == color_name)
+            .map(|&(_, color)| color)
+            .ok_or_else(|| {
+                let names: Vec<&str> = COLOR_NAMES.iter().map(|(name, _)| *name).collect();
+                format!(
+                    "unknown color '{}', expected one of {}",
+                    color_name,
+                    names.join(", ")
+                )
+            })?;
+
+        match which.as_str() {
+            "fg" => pair.foreground = color,
+            "bg" => pair.background = color,
+            _ => return Err(format!("unknown setting '{}', expected fg or bg", which)),
+        }
+    }
+
+    Ok(())
+}
+
+fn keymap_mode(name: &str) -> Result<InputMode, String> {
+    keymap::MODE_NAMES
+        .iter()
+        .find(|(mode_name, _)| *mode_name == name)
+        .map(|&(_, mode)| mode)
+        .ok_or_else(|| {
+            let names: Vec<&str> = keymap::MODE_NAMES.iter().map(|(name, _)| *name).collect();
+            format!("unknown mode, expected one of {}", names.join(", "))
+        })
+}
+
+/// Reads `leader`, the key that `<leader>` stands for, and `timeout`, how
+/// many milliseconds there can be between the keys of a sequence, from
+/// `[keys]`.
+fn set_key_options(keymap: &mut Keymap, keys: &Value) -> Result<(), (&'static str, String)> {
+    if let Some(value) = keys.get("leader") {
+        let problem = |problem| ("leader", problem);
+        let leader = keymap
+            .parse_keys(string(value).map_err(problem)?)
+            .filter(|keys| keys.len() == 1)
+            .ok_or_else(|| problem("expected a single key".to_string()))?;
+
+        keymap.set_leader(leader[0]);
+    }
+
+    if let Some(value) = keys.get("timeout") {
+        let milliseconds = value
+            .as_integer()
+            .filter(|milliseconds| *milliseconds >= 0)
+            .ok_or_else(|| ("timeout", expected("a number of milliseconds", value)))?;
+
+        keymap.timeout = match milliseconds {
+            0 => None,
+            _ => Some(Duration::from_millis(milliseconds as u64)),
+        };
+    }
+
+    Ok(())
+}
+
+/// Binds a sequence of keys from a table like `[keys.normal]` to the name of
+/// a binding, or unbinds it if the name is `"none"`.
+fn bind_key(
+    keymap: &mut Keymap,
+    (mode_name, mode): (&str, InputMode),
+    key_text: &str,
+    value: &Value,
+) -> Result<(), String> {
+    let keys = keymap
+        .parse_keys(key_text)
+        .ok_or_else(|| "unknown key, expected characters or names like <c-p>".to_string())?;
+
+    if let Key::Char('0'..='9') = keys[0] {
+        return Err("digits are used for counts".to_string());
+    }
+
+    let name = string(value)?;
+
+    if name == "none" {
+        keymap.unbind(mode, &keys);
+        return Ok(());
+    }
+
+    let binding = Binding::from_name(name).ok_or_else(|| format!("unknown binding '{}'", name))?;
+
+    if !binding.works_in(mode) {
+        return Err(format!("'{}' can't be used in {} mode", name, mode_name));
+    }
+
+    keymap.bind(mode, &keys, binding);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::keymap::Lookup;
+
+    fn parse(text: &str) -> Result<Config, String> {
+        Config::parse(text, Path::new("config.toml")).map_err(|error| error.to_string())
+    }
+
+    #[test]
+    fn config_parsing() {
+        let config = parse(
+            r#"
+            [options]
+            hidden = true
+            dirsfirst = false
+            sort = "size"
+            ignore = ["*.pyc", "target"]
+
+            [colors]
+            cursor = { fg = "white", bg = "blue" }
+
+            [keys]
+            leader = "<space>"
+
+            [keys.normal]
+            J = "down"
+            "<leader>f" = "finder"
+            x = "none"
+            "#,
+        )
+        .unwrap();
+
+        assert!(config.settings.show_hidden);
+        assert!(!config.settings.sort.directories_first);
+        assert_eq!(config.settings.sort.key, SortKey::Size);
+        assert_eq!(config.settings.ignore_patterns, vec!["*.pyc", "target"]);
+        assert_eq!(config.theme.cursor.background, Color::Blue);
+
+        let lookup = |keys: &[Key]| config.keymap.lookup(InputMode::Normal, keys);
+        assert_eq!(
+            lookup(&[Key::Char('J')]),
+            Lookup::Bound(Binding::Down, None)
+        );
+        assert_eq!(
+            lookup(&[Key::Char(' '), Key::Char('f')]),
+            Lookup::Bound(Binding::Finder, None)
+        );
+        assert_eq!(lookup(&[Key::Char('x')]), Lookup::Unbound);
+        assert_eq!(
+            lookup(&[Key::Char('j')]),
+            Lookup::Bound(Binding::Down, None)
+        );
+    }
+
+    #[test]
+    fn config_errors() {
+        assert_eq!(
+            parse("[options]\nhidden = \"yes\"").unwrap_err(),
+            "Error in config file config.toml: options.hidden: expected true or false, found string"
+        );
+        assert_eq!(
+            parse("[keys.normal]\nJ = \"dwon\"").unwrap_err(),
+            "Error in config file config.toml: keys.normal.J: unknown binding 'dwon'"
+        );
+        assert!(parse("[keys.visual]\nq = \"quit\"").is_err());
+        assert!(parse("[keys.normal]\n3x = \"down\"").is_err());
+        assert!(parse("[keys]\nleader = \"ab\"").is_err());
+        assert!(parse("[keys.insert]\nq = \"quit\"").is_err());
+        assert!(parse("[colors.cursor]\nfg = \"mauve\"").is_err());
+        assert!(parse("[options]\nsort = \"sideways\"").is_err());
+        assert!(parse("[options]\nfrobnicate = true").is_err());
+        assert!(parse("[options\nhidden = true").is_err());
+    }
+}
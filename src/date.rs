@@ -0,0 +1,123 @@
+//! Just enough calendar math to show timestamps, so that we don't need to
+//! pull in a date library.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A point in time broken down into calendar fields. The local offset can't
+/// be found portably without platform APIs, so this is in UTC unless it comes
+/// from `local_from_system_time`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl DateTime {
+    pub fn from_system_time(time: SystemTime) -> DateTime {
+        let seconds = time
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+
+        let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+        let seconds_of_day = (seconds % 86_400) as u32;
+
+        DateTime {
+            year,
+            month,
+            day,
+            hour: seconds_of_day / 3600,
+            minute: seconds_of_day / 60 % 60,
+            second: seconds_of_day % 60,
+        }
+    }
+
+    /// Like `from_system_time`, but in the local time zone. Only Unix-like
+    /// platforms can tell us what that is, so other platforms get UTC.
+    #[cfg(unix)]
+    pub fn local_from_system_time(time: SystemTime) -> DateTime {
+        let seconds = time
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0) as libc::time_t;
+
+        // localtime_r is the thread-safe version of localtime, and fills in
+        // the fields we hand it.
+        let mut fields: libc::tm = unsafe { std::mem::zeroed() };
+
+        if unsafe { libc::localtime_r(&seconds, &mut fields) }.is_null() {
+            return DateTime::from_system_time(time);
+        }
+
+        DateTime {
+            year: i64::from(fields.tm_year) + 1900,
+            month: (fields.tm_mon + 1) as u32,
+            day: fields.tm_mday as u32,
+            hour: fields.tm_hour as u32,
+            minute: fields.tm_min as u32,
+            second: fields.tm_sec as u32,
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn local_from_system_time(time: SystemTime) -> DateTime {
+        DateTime::from_system_time(time)
+    }
+
+    /// Formats the time like `2019-03-01T12:30:00`.
+    pub fn to_iso8601(&self) -> String {
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+
+    /// Formats the time like `2019-03-01 12:30`, for showing in the listing.
+    pub fn to_short_string(&self) -> String {
+        format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute
+        )
+    }
+}
+
+/// Converts a count of days since 1970-01-01 into a year, month, and day.
+/// This is Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = if days >= 0 { days } else { days - 146_096 } / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn date_formatting() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_551_443_400);
+        let date = DateTime::from_system_time(time);
+
+        assert_eq!(date.to_iso8601(), "2019-03-01T12:30:00");
+        assert_eq!(date.to_short_string(), "2019-03-01 12:30");
+    }
+}
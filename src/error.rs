@@ -0,0 +1,203 @@
+use std::{fmt, io, path::PathBuf};
+
+use crate::{overlay::describe_exit, sort::SortKey};
+
+/// An error that happened while carrying out an action. Errors are shown to
+/// the user in the status bar instead of taking down the whole program.
+#[derive(Debug)]
+pub enum AppError {
+    ReadDirectory {
+        path: PathBuf,
+        source: io::Error,
+    },
+    RemoveFile {
+        path: PathBuf,
+        source: io::Error,
+    },
+    RemoveDirectory {
+        path: PathBuf,
+        source: io::Error,
+    },
+    CreateFile {
+        path: PathBuf,
+        source: io::Error,
+    },
+    CreateDirectory {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Rename {
+        path: PathBuf,
+        source: io::Error,
+    },
+    AlreadyExists {
+        path: PathBuf,
+    },
+    Copy {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Move {
+        path: PathBuf,
+        source: io::Error,
+    },
+    PasteIntoItself {
+        path: PathBuf,
+    },
+    Trash {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Restore {
+        path: PathBuf,
+        source: io::Error,
+    },
+    ListTrash {
+        source: io::Error,
+    },
+    NoTrash,
+    Undo {
+        path: PathBuf,
+        source: io::Error,
+    },
+    UnsafeUndo {
+        path: PathBuf,
+        reason: &'static str,
+    },
+    UnsafeDelete {
+        path: PathBuf,
+    },
+    NothingToUndo,
+    NothingToRedo,
+    NothingToRepeat,
+    Bookmarks {
+        path: PathBuf,
+        source: io::Error,
+    },
+    NoSuchBookmark(char),
+    NoDataDirectory,
+    UnknownCommand(String),
+    UnclosedQuote(char),
+    Usage(&'static str),
+    InvalidRegex {
+        pattern: String,
+        source: regex::Error,
+    },
+    RunProgram {
+        program: String,
+        source: io::Error,
+    },
+    ProgramFailed {
+        program: String,
+        code: Option<i32>,
+    },
+    UnknownOption(String),
+    UnknownSortKey(String),
+    InvalidPattern {
+        pattern: String,
+        source: globset::Error,
+    },
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, output: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AppError::ReadDirectory { path, source } => {
+                write!(output, "Could not read {}: {}", path.display(), source)
+            }
+            AppError::RemoveFile { path, source } => {
+                write!(output, "Could not remove {}: {}", path.display(), source)
+            }
+            AppError::RemoveDirectory { path, source } => write!(
+                output,
+                "Could not remove directory {}: {}",
+                path.display(),
+                source
+            ),
+            AppError::CreateFile { path, source } => {
+                write!(output, "Could not create {}: {}", path.display(), source)
+            }
+            AppError::CreateDirectory { path, source } => write!(
+                output,
+                "Could not create directory {}: {}",
+                path.display(),
+                source
+            ),
+            AppError::Rename { path, source } => {
+                write!(output, "Could not rename {}: {}", path.display(), source)
+            }
+            AppError::AlreadyExists { path } => {
+                write!(output, "{} already exists", path.display())
+            }
+            AppError::Copy { path, source } => {
+                write!(output, "Could not copy {}: {}", path.display(), source)
+            }
+            AppError::Move { path, source } => {
+                write!(output, "Could not move {}: {}", path.display(), source)
+            }
+            AppError::PasteIntoItself { path } => {
+                write!(output, "Can't paste {} into itself", path.display())
+            }
+            AppError::Trash { path, source } => {
+                write!(output, "Could not trash {}: {}", path.display(), source)
+            }
+            AppError::Restore { path, source } => {
+                write!(output, "Could not restore {}: {}", path.display(), source)
+            }
+            AppError::ListTrash { source } => {
+                write!(output, "Could not list the trash: {}", source)
+            }
+            AppError::Undo { path, source } => {
+                write!(output, "Could not undo {}: {}", path.display(), source)
+            }
+            AppError::UnsafeUndo { path, reason } => write!(
+                output,
+                "Refusing to touch {} because {}",
+                path.display(),
+                reason
+            ),
+            AppError::UnsafeDelete { path } => write!(
+                output,
+                "Refusing to delete {} because it isn't inside the current directory",
+                path.display()
+            ),
+            AppError::NothingToUndo => write!(output, "Nothing to undo"),
+            AppError::NothingToRedo => write!(output, "Nothing to redo"),
+            AppError::NothingToRepeat => write!(output, "Nothing to repeat"),
+            AppError::Bookmarks { path, source } => write!(
+                output,
+                "Could not use bookmarks in {}: {}",
+                path.display(),
+                source
+            ),
+            AppError::NoSuchBookmark(letter) => write!(output, "No bookmark '{}'", letter),
+            AppError::NoDataDirectory => write!(output, "Could not find a data directory"),
+            AppError::UnknownCommand(command) => write!(output, "Unknown command: {}", command),
+            AppError::UnclosedQuote(quote) => write!(output, "Missing a closing {}", quote),
+            AppError::Usage(usage) => write!(output, "Usage: {}", usage),
+            AppError::InvalidRegex { pattern, source } => {
+                write!(output, "Invalid regex {}: {}", pattern, source)
+            }
+            AppError::RunProgram { program, source } => {
+                write!(output, "Could not run {}: {}", program, source)
+            }
+            AppError::ProgramFailed { program, code } => {
+                write!(output, "{} {}", program, describe_exit(*code))
+            }
+            AppError::UnknownOption(option) => write!(output, "Unknown option: {}", option),
+            AppError::InvalidPattern { pattern, source } => {
+                write!(output, "Invalid pattern {}: {}", pattern, source)
+            }
+            AppError::UnknownSortKey(key) => {
+                let names: Vec<&str> = SortKey::ALL.iter().map(|key| key.name()).collect();
+                write!(
+                    output,
+                    "Unknown sort order '{}', expected one of {}",
+                    key,
+                    names.join(", ")
+                )
+            }
+            AppError::NoTrash => write!(output, "Could not find a trash directory"),
+        }
+    }
+}
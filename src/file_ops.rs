@@ -0,0 +1,246 @@
+//! Filesystem operations that the standard library doesn't provide directly,
+//! like copying whole directory trees.
+
+use std::{
+    ffi::OsStr,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Copies a file or a directory and all of its contents. Symbolic links are
+/// copied as links instead of being followed.
+pub fn copy_recursive(from: &Path, to: &Path) -> io::Result<()> {
+    let file_type = fs::symlink_metadata(from)?.file_type();
+
+    if file_type.is_dir() {
+        fs::create_dir(to)?;
+
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else if file_type.is_symlink() {
+        copy_symlink(from, to)?;
+    } else {
+        fs::copy(from, to)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(from)?, to)
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    fs::copy(from, to).map(|_| ())
+}
+
+/// Moves a file or directory. Renaming only works within one filesystem, so
+/// moving to another one falls back to copying and then removing the
+/// original. Any other error from renaming is returned as it is.
+pub fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(ref error) if is_cross_device(error) => {}
+        result => return result,
+    }
+
+    let existed = path_exists(to);
+
+    if let Err(error) = copy_recursive(from, to) {
+        // Don't leave half of a copy behind.
+        if !existed {
+            let _ = remove_path(to);
+        }
+
+        return Err(error);
+    }
+
+    remove_path(from)
+}
+
+#[cfg(unix)]
+fn is_cross_device(error: &io::Error) -> bool {
+    error.raw_os_error() == Some(libc::EXDEV)
+}
+
+#[cfg(windows)]
+fn is_cross_device(error: &io::Error) -> bool {
+    const ERROR_NOT_SAME_DEVICE: i32 = 17;
+
+    error.raw_os_error() == Some(ERROR_NOT_SAME_DEVICE)
+}
+
+/// Removes a file, a symbolic link, or a directory and all of its contents.
+pub fn remove_path(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.file_type().is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Turns a path typed by the user into a full one. Relative paths start from
+/// `base`, and a leading `~` means the home directory.
+pub fn resolve_path(base: &Path, path: &str) -> PathBuf {
+    if path == "~" || path.starts_with("~/") {
+        if let Some(home) = dirs::home_dir() {
+            return home.join(path[1..].trim_start_matches('/'));
+        }
+    }
+
+    base.join(path)
+}
+
+/// Whether anything, including a broken symbolic link, exists at the path.
+pub fn path_exists(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok()
+}
+
+/// Whether two paths lead to the very same entry, like `foo` and `FOO` do on
+/// a case-insensitive filesystem. Symbolic links aren't followed.
+#[cfg(unix)]
+pub fn is_same_entry(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (fs::symlink_metadata(a), fs::symlink_metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn is_same_entry(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Produces a name like `name (1).ext` to use when `name.ext` is taken.
+pub fn numbered_name(name: &OsStr, number: usize) -> String {
+    let path = Path::new(name);
+    let stem = path
+        .file_stem()
+        .unwrap_or(name)
+        .to_string_lossy()
+        .to_string();
+
+    match path.extension() {
+        Some(extension) => format!("{} ({}).{}", stem, number, extension.to_string_lossy()),
+        None => format!("{} ({})", stem, number),
+    }
+}
+
+/// Finds a path in `directory` for an entry called `name` that doesn't collide
+/// with anything already there.
+pub fn unique_path(directory: &Path, name: &OsStr) -> PathBuf {
+    unique_paths(directory, name, 1).remove(0)
+}
+
+/// Like `unique_path`, but finds `count` different paths that are all free,
+/// numbering them in order.
+pub fn unique_paths(directory: &Path, name: &OsStr, count: usize) -> Vec<PathBuf> {
+    let mut paths = Vec::with_capacity(count);
+    let mut path = directory.join(name);
+    let mut number = 1;
+
+    while paths.len() < count {
+        if !path_exists(&path) {
+            paths.push(path);
+        }
+
+        path = directory.join(numbered_name(name, number));
+        number += 1;
+    }
+
+    paths
+}
+
+/// An empty directory for a test to work in, removed along with everything
+/// in it when dropped.
+#[cfg(test)]
+pub struct TempDirectory {
+    path: PathBuf,
+}
+
+#[cfg(test)]
+impl TempDirectory {
+    pub fn new(name: &str) -> TempDirectory {
+        let path = std::env::temp_dir().join(format!("msb-{}-{}", name, std::process::id()));
+
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        TempDirectory { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDirectory {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn numbered_names() {
+        assert_eq!(numbered_name(OsStr::new("foo.txt"), 1), "foo (1).txt");
+        assert_eq!(numbered_name(OsStr::new("foo"), 2), "foo (2)");
+        assert_eq!(numbered_name(OsStr::new(".bashrc"), 1), ".bashrc (1)");
+    }
+
+    #[test]
+    fn unique_names() {
+        let directory = TempDirectory::new("unique-names");
+        let name = OsStr::new("notes.txt");
+
+        assert_eq!(
+            unique_paths(directory.path(), name, 2),
+            vec![
+                directory.path().join("notes.txt"),
+                directory.path().join("notes (1).txt")
+            ]
+        );
+
+        fs::write(directory.path().join("notes.txt"), "").unwrap();
+        fs::write(directory.path().join("notes (2).txt"), "").unwrap();
+
+        assert_eq!(
+            unique_paths(directory.path(), name, 2),
+            vec![
+                directory.path().join("notes (1).txt"),
+                directory.path().join("notes (3).txt")
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_moves() {
+        let directory = TempDirectory::new("failed-moves");
+        let folder = directory.path().join("folder");
+        fs::create_dir(&folder).unwrap();
+
+        let missing = move_path(
+            &directory.path().join("missing"),
+            &directory.path().join("moved"),
+        );
+        assert_eq!(missing.unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert!(!path_exists(&directory.path().join("moved")));
+
+        // Renaming a folder into itself fails, and must not turn into a copy.
+        assert!(move_path(&folder, &folder.join("inside")).is_err());
+        assert!(!path_exists(&folder.join("inside")));
+        assert!(path_exists(&folder));
+    }
+}
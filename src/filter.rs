@@ -0,0 +1,136 @@
+//! Decides which entries are left out of the listing: hidden files, entries
+//! matched by `.gitignore` and `.ignore` files, and the user's own patterns.
+
+use std::path::Path;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match, WalkBuilder,
+};
+
+use crate::settings::Settings;
+
+pub struct EntryFilter {
+    show_hidden: bool,
+
+    /// Ignore rules from this directory and its ancestors, deepest first so
+    /// that rules closer to an entry win.
+    ignore_files: Vec<Gitignore>,
+
+    patterns: GlobSet,
+}
+
+impl EntryFilter {
+    pub fn new(directory: &Path, settings: &Settings) -> EntryFilter {
+        let ignore_files = if settings.use_ignore_files {
+            read_ignore_files(directory)
+        } else {
+            Vec::new()
+        };
+
+        EntryFilter {
+            show_hidden: settings.show_hidden,
+            ignore_files,
+            patterns: build_patterns(settings),
+        }
+    }
+
+    pub fn is_visible(&self, path: &Path, is_directory: bool) -> bool {
+        let name = match path.file_name() {
+            Some(name) => name,
+            None => return true,
+        };
+
+        if !self.show_hidden && name.to_string_lossy().starts_with('.') {
+            return false;
+        }
+
+        if self.patterns.is_match(name) {
+            return false;
+        }
+
+        // Only the entry itself is checked, not its parents, so browsing
+        // inside an ignored directory still shows what's in it.
+        for ignore_file in &self.ignore_files {
+            match ignore_file.matched(path, is_directory) {
+                Match::Ignore(_) => return false,
+                Match::Whitelist(_) => return true,
+                Match::None => {}
+            }
+        }
+
+        true
+    }
+}
+
+/// Sets up a walk of the whole tree under `root` for searching it. Unlike the
+/// listing, ignore files are always respected, since searching through build
+/// output and dependencies is rarely what anyone wants.
+pub fn walker(root: &Path, settings: &Settings) -> WalkBuilder {
+    let patterns = build_patterns(settings);
+    let mut walker = WalkBuilder::new(root);
+
+    walker
+        .hidden(!settings.show_hidden)
+        .filter_entry(move |entry| {
+            let name = entry.file_name();
+            name != ".git" && !patterns.is_match(name)
+        });
+
+    walker
+}
+
+fn build_patterns(settings: &Settings) -> GlobSet {
+    let mut patterns = GlobSetBuilder::new();
+
+    // Patterns are checked when they're set, so any that fail here can safely
+    // be skipped.
+    for pattern in &settings.ignore_patterns {
+        if let Ok(glob) = Glob::new(pattern) {
+            patterns.add(glob);
+        }
+    }
+
+    patterns.build().unwrap_or_else(|_| GlobSet::empty())
+}
+
+/// Reads the ignore files that apply to a directory. `.ignore` files apply
+/// anywhere, but like Git, `.gitignore` files only count inside a repository.
+fn read_ignore_files(directory: &Path) -> Vec<Gitignore> {
+    let repository_root = directory
+        .ancestors()
+        .find(|ancestor| ancestor.join(".git").exists());
+
+    let mut ignore_files = Vec::new();
+
+    for ancestor in directory.ancestors() {
+        let mut builder = GitignoreBuilder::new(ancestor);
+        let is_root = Some(ancestor) == repository_root;
+
+        // Rules added later take priority, so these go from weakest to
+        // strongest. Broken lines are skipped rather than failing the whole
+        // file.
+        if is_root {
+            builder.add(ancestor.join(".git").join("info").join("exclude"));
+        }
+
+        if repository_root.is_some() {
+            builder.add(ancestor.join(".gitignore"));
+        }
+
+        builder.add(ancestor.join(".ignore"));
+
+        if let Ok(ignore_file) = builder.build() {
+            if !ignore_file.is_empty() {
+                ignore_files.push(ignore_file);
+            }
+        }
+
+        if is_root {
+            break;
+        }
+    }
+
+    ignore_files
+}
@@ -0,0 +1,219 @@
+//! Finds files anywhere under a directory by fuzzy matching their paths, like
+//! fzf. The directory tree is walked on a background thread so that a big
+//! tree doesn't freeze the UI, and results show up as they're found.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+};
+
+use crate::{
+    filter,
+    matcher::{MatchMode, Matcher},
+    settings::Settings,
+};
+
+/// How many results are ranked and shown at once.
+const MAX_RESULTS: usize = 1000;
+
+/// How many paths the walker collects before sending them over.
+const BATCH_SIZE: usize = 256;
+
+#[derive(Debug)]
+pub struct Finder {
+    root: PathBuf,
+    receiver: Receiver<Vec<PathBuf>>,
+
+    /// Every file found so far, relative to `root`.
+    paths: Vec<PathBuf>,
+
+    done: bool,
+}
+
+impl Finder {
+    /// Starts walking the tree under `root` in the background.
+    pub fn start(root: PathBuf, settings: &Settings) -> Finder {
+        let (sender, receiver) = mpsc::channel();
+
+        let walker = filter::walker(&root, settings);
+        let walk_root = root.clone();
+        thread::spawn(move || {
+            let mut batch = Vec::with_capacity(BATCH_SIZE);
+
+            // Entries that can't be read are skipped rather than stopping the
+            // whole walk.
+            for entry in walker.build().filter_map(Result::ok) {
+                let is_file = entry
+                    .file_type()
+                    .map(|file_type| !file_type.is_dir())
+                    .unwrap_or(false);
+
+                if !is_file {
+                    continue;
+                }
+
+                if let Ok(relative) = entry.path().strip_prefix(&walk_root) {
+                    batch.push(relative.to_path_buf());
+                }
+
+                if batch.len() == BATCH_SIZE {
+                    let full_batch = std::mem::replace(&mut batch, Vec::with_capacity(BATCH_SIZE));
+
+                    // The finder was closed, so nobody wants the rest.
+                    if sender.send(full_batch).is_err() {
+                        return;
+                    }
+                }
+            }
+
+            let _ = sender.send(batch);
+        });
+
+        Finder {
+            root,
+            receiver,
+            paths: Vec::new(),
+            done: false,
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// How many files have been found so far.
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// Whether the whole tree has been walked.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Picks up whatever the walker has found since the last poll, returning
+    /// whether anything changed.
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+
+        loop {
+            match self.receiver.try_recv() {
+                Ok(batch) => {
+                    self.paths.extend(batch);
+                    changed = true;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    changed |= !self.done;
+                    self.done = true;
+                    break;
+                }
+            }
+        }
+
+        changed
+    }
+
+    /// Ranks the files found so far against the query, best first. Shorter
+    /// paths win ties, since they're usually what was meant.
+    pub fn rank(&self, query: &str) -> Vec<PathBuf> {
+        if query.is_empty() {
+            return self.paths.iter().take(MAX_RESULTS).cloned().collect();
+        }
+
+        let matcher = Matcher::new(MatchMode::Fuzzy, query);
+        let mut ranked: Vec<(i64, usize, &PathBuf)> = self
+            .paths
+            .iter()
+            .filter_map(|path| {
+                let text = path.to_string_lossy();
+
+                matcher
+                    .find(&text)
+                    .map(|found| (-found.score, text.len(), path))
+            })
+            .collect();
+
+        ranked.sort();
+        ranked
+            .into_iter()
+            .take(MAX_RESULTS)
+            .map(|(_, _, path)| path.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::{fs, time::Duration};
+
+    use crate::file_ops::TempDirectory;
+
+    /// Finds every file under the directory, waiting for the walk to finish.
+    fn finished_finder(directory: &Path) -> Finder {
+        let mut finder = Finder::start(directory.to_path_buf(), &Settings::default());
+
+        while !finder.is_done() {
+            finder.poll();
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        finder
+    }
+
+    #[test]
+    fn ranking() {
+        let directory = TempDirectory::new("finder-ranking");
+
+        for path in &[
+            "main.rs",
+            "src/main.rs",
+            "lib/other/main.rs",
+            "docs/mountain.txt",
+            "readme.md",
+        ] {
+            let path = directory.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        let finder = finished_finder(directory.path());
+        assert_eq!(finder.len(), 5);
+
+        // Matching at the start of the path scores the same as at the start
+        // of a file name, so these tie and shorter paths go first. Gaps in
+        // the match score worse, and paths that don't match are left out.
+        assert_eq!(
+            finder.rank("main"),
+            vec![
+                PathBuf::from("main.rs"),
+                PathBuf::from("src/main.rs"),
+                PathBuf::from("lib/other/main.rs"),
+                PathBuf::from("docs/mountain.txt"),
+            ]
+        );
+
+        assert!(finder.rank("zzz").is_empty());
+
+        // Without a query, every file is listed in the order it was found.
+        let mut everything = finder.rank("");
+        everything.sort();
+        assert_eq!(
+            everything,
+            vec![
+                PathBuf::from("docs/mountain.txt"),
+                PathBuf::from("lib/other/main.rs"),
+                PathBuf::from("main.rs"),
+                PathBuf::from("readme.md"),
+                PathBuf::from("src/main.rs"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//! Programs that take over the terminal while they run, like text editors.
+//! The UI steps out of the way until they exit.
+
+use std::{
+    env,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+};
+
+use crate::{
+    shell,
+    terminal_context::{Key, TerminalContext},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForegroundCommand {
+    pub program: String,
+    pub args: Vec<String>,
+    pub directory: PathBuf,
+
+    /// The command line, when this runs one through the shell instead of
+    /// `program` with `args`. Its output is left on screen until the user
+    /// presses return.
+    pub shell_command: Option<String>,
+}
+
+impl ForegroundCommand {
+    /// Opens files in the editor from `$VISUAL` or `$EDITOR`, falling back
+    /// to `vi`. Given a line, the editor is asked to jump to it with `+line`,
+    /// which most terminal editors understand.
+    pub fn editor(paths: &[PathBuf], line: Option<usize>, directory: &Path) -> ForegroundCommand {
+        let mut command = ForegroundCommand::from_variable(&["VISUAL", "EDITOR"], "vi", directory);
+
+        if let Some(line) = line {
+            command.args.push(format!("+{}", line));
+        }
+
+        command.add_paths(paths);
+        command
+    }
+
+    /// Shows files in the pager from `$PAGER`, falling back to `less`.
+    pub fn pager(paths: &[PathBuf], directory: &Path) -> ForegroundCommand {
+        let mut command = ForegroundCommand::from_variable(&["PAGER"], "less", directory);
+        command.add_paths(paths);
+        command
+    }
+
+    /// Runs a command line through the shell.
+    pub fn shell(command: &str, directory: &Path) -> ForegroundCommand {
+        ForegroundCommand {
+            program: shell::SHELL.to_string(),
+            args: Vec::new(),
+            directory: directory.to_path_buf(),
+            shell_command: Some(command.to_string()),
+        }
+    }
+
+    /// Starts the user's shell from `$SHELL` to use until they exit it.
+    #[cfg(not(windows))]
+    pub fn interactive_shell(directory: &Path) -> ForegroundCommand {
+        ForegroundCommand::from_variable(&["SHELL"], "sh", directory)
+    }
+
+    #[cfg(windows)]
+    pub fn interactive_shell(directory: &Path) -> ForegroundCommand {
+        ForegroundCommand::from_variable(&["COMSPEC"], "cmd", directory)
+    }
+
+    /// What to call the command in messages.
+    pub fn name(&self) -> &str {
+        match &self.shell_command {
+            Some(command) => command,
+            None => &self.program,
+        }
+    }
+
+    fn add_paths(&mut self, paths: &[PathBuf]) {
+        self.args
+            .extend(paths.iter().map(|path| path.to_string_lossy().to_string()));
+    }
+
+    /// Builds a command from the first environment variable that's set,
+    /// which can include arguments, like `code --wait`.
+    fn from_variable(variables: &[&str], fallback: &str, directory: &Path) -> ForegroundCommand {
+        let value = variables
+            .iter()
+            .filter_map(|variable| env::var(variable).ok())
+            .find(|value| !value.trim().is_empty())
+            .unwrap_or_else(|| fallback.to_string());
+
+        let mut words = value.split_whitespace().map(str::to_string);
+
+        ForegroundCommand {
+            program: words.next().unwrap_or_else(|| fallback.to_string()),
+            args: words.collect(),
+            directory: directory.to_path_buf(),
+            shell_command: None,
+        }
+    }
+
+    /// Runs the command with the terminal as its input. The UI should be
+    /// suspended first.
+    pub fn run(&self, context: &mut TerminalContext) -> io::Result<ExitStatus> {
+        let mut process = match &self.shell_command {
+            Some(command) => shell::shell_command(command),
+            None => {
+                let mut process = Command::new(&self.program);
+                process.args(&self.args);
+                process
+            }
+        };
+
+        let status = process
+            .current_dir(&self.directory)
+            .stdin(context.stdin_for_child()?)
+            .status();
+
+        if self.shell_command.is_some() {
+            print!("\nPress return to continue");
+            io::stdout().flush()?;
+
+            // The terminal isn't stdin anymore, so the key is read the same
+            // way as any other.
+            while let Some(key) = context.read_key(None) {
+                if key == Key::Char('\n') {
+                    break;
+                }
+            }
+        }
+
+        status
+    }
+}
@@ -0,0 +1,250 @@
+//! Searches the contents of every file under a directory on a background
+//! thread, like `grep -r`.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+};
+
+use regex::{Regex, RegexBuilder};
+
+use crate::{filter, settings::Settings};
+
+/// The search stops after finding this many matches.
+const MAX_MATCHES: usize = 10_000;
+
+/// Files bigger than this are skipped, since they're rarely source code.
+const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// How much of the start of a file is checked for NUL bytes to decide if it's
+/// binary, the same way Git does.
+const BINARY_CHECK_SIZE: usize = 8000;
+
+/// Long lines are cut down to this many characters for display.
+const MAX_LINE_LENGTH: usize = 200;
+
+/// One line that matched the pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrepMatch {
+    /// The file the match is in, relative to where the search started.
+    pub path: PathBuf,
+
+    /// Which line matched, counting from 1.
+    pub line_number: usize,
+
+    pub text: String,
+}
+
+#[derive(Debug)]
+pub struct Grep {
+    root: PathBuf,
+    pattern: String,
+    receiver: Receiver<Vec<GrepMatch>>,
+    done: bool,
+}
+
+impl Grep {
+    /// Starts searching the files under `root` for a regular expression.
+    /// Like the find prompt, the search ignores case unless the pattern has
+    /// upper case letters in it.
+    pub fn start(root: PathBuf, pattern: &str, settings: &Settings) -> Result<Grep, regex::Error> {
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(!pattern.chars().any(char::is_uppercase))
+            .build()?;
+
+        let (sender, receiver) = mpsc::channel();
+        let walker = filter::walker(&root, settings);
+        let walk_root = root.clone();
+
+        thread::spawn(move || {
+            let mut found = 0;
+
+            for entry in walker.build().filter_map(Result::ok) {
+                let is_file = entry
+                    .file_type()
+                    .map(|file_type| file_type.is_file())
+                    .unwrap_or(false);
+
+                if !is_file {
+                    continue;
+                }
+
+                let relative = match entry.path().strip_prefix(&walk_root) {
+                    Ok(relative) => relative,
+                    Err(_) => continue,
+                };
+
+                let mut matches = search_file(entry.path(), relative, &regex);
+                matches.truncate(MAX_MATCHES - found);
+                found += matches.len();
+
+                // If sending fails, the search was closed and nobody wants
+                // the rest of the results.
+                if !matches.is_empty() && sender.send(matches).is_err() {
+                    return;
+                }
+
+                if found >= MAX_MATCHES {
+                    return;
+                }
+            }
+        });
+
+        Ok(Grep {
+            root,
+            pattern: pattern.to_string(),
+            receiver,
+            done: false,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Whether every file has been searched.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Adds whatever the search has found since the last poll to `matches`,
+    /// returning whether anything changed.
+    pub fn poll(&mut self, matches: &mut Vec<GrepMatch>) -> bool {
+        let mut changed = false;
+
+        loop {
+            match self.receiver.try_recv() {
+                Ok(batch) => {
+                    matches.extend(batch);
+                    changed = true;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    changed |= !self.done;
+                    self.done = true;
+                    break;
+                }
+            }
+        }
+
+        changed
+    }
+}
+
+/// Finds the lines in one file that match. Files that can't be read, are too
+/// big, or look binary have no matches.
+fn search_file(path: &Path, relative: &Path, regex: &Regex) -> Vec<GrepMatch> {
+    let too_big = fs::metadata(path)
+        .map(|metadata| metadata.len() > MAX_FILE_SIZE)
+        .unwrap_or(true);
+
+    if too_big {
+        return Vec::new();
+    }
+
+    let contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(_) => return Vec::new(),
+    };
+
+    let check_size = contents.len().min(BINARY_CHECK_SIZE);
+    if contents[..check_size].contains(&0) {
+        return Vec::new();
+    }
+
+    String::from_utf8_lossy(&contents)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| regex.is_match(line))
+        .map(|(index, line)| GrepMatch {
+            path: relative.to_path_buf(),
+            line_number: index + 1,
+            text: clean_line(line),
+        })
+        .collect()
+}
+
+/// Makes a line safe to draw: tabs and other control characters would throw
+/// off the layout of the screen.
+fn clean_line(line: &str) -> String {
+    line.trim()
+        .chars()
+        .take(MAX_LINE_LENGTH)
+        .map(|char| if char.is_control() { ' ' } else { char })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::file_ops::TempDirectory;
+
+    fn search(directory: &TempDirectory, name: &str, contents: &[u8]) -> Vec<GrepMatch> {
+        let path = directory.path().join(name);
+        fs::write(&path, contents).unwrap();
+
+        search_file(&path, Path::new(name), &Regex::new("needle").unwrap())
+    }
+
+    #[test]
+    fn searching_files() {
+        let directory = TempDirectory::new("grep-search-file");
+
+        let matches = search(&directory, "crlf", b"hay\r\n\tneedle\r\nhay\r\nneedle\r\n");
+        assert_eq!(
+            matches,
+            vec![
+                GrepMatch {
+                    path: PathBuf::from("crlf"),
+                    line_number: 2,
+                    text: "needle".to_string(),
+                },
+                GrepMatch {
+                    path: PathBuf::from("crlf"),
+                    line_number: 4,
+                    text: "needle".to_string(),
+                },
+            ]
+        );
+
+        let mut binary = b"needle\n".to_vec();
+        binary.push(0);
+        assert!(search(&directory, "binary", &binary).is_empty());
+
+        // A NUL byte past the part that's checked doesn't make it binary.
+        let mut late_binary = vec![b'\n'; BINARY_CHECK_SIZE];
+        late_binary.extend(b"needle\n\0");
+        let matches = search(&directory, "late-binary", &late_binary);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].line_number, BINARY_CHECK_SIZE + 1);
+
+        let long_line = format!("needle{}\n", "x".repeat(1000));
+        let matches = search(&directory, "long", long_line.as_bytes());
+        assert_eq!(matches[0].text.chars().count(), MAX_LINE_LENGTH);
+
+        let missing = directory.path().join("missing");
+        assert!(search_file(
+            &missing,
+            Path::new("missing"),
+            &Regex::new("needle").unwrap()
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn cleaning_lines() {
+        assert_eq!(clean_line("  \tindented\t"), "indented");
+        assert_eq!(clean_line("a\tb\rc\u{1b}[0m"), "a b c [0m");
+        assert_eq!(clean_line("日本語"), "日本語");
+
+        let long_line = "日".repeat(MAX_LINE_LENGTH + 10);
+        assert_eq!(clean_line(&long_line), "日".repeat(MAX_LINE_LENGTH));
+    }
+}
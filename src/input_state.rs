@@ -1,18 +1,40 @@
+use std::time::{Duration, Instant};
+
 use all_term::Key;
 
-use crate::{action::Action, terminal_context::TerminalContext};
+use crate::{
+    action::{Action, ConflictResolution},
+    app_state::AppState,
+    completion,
+    keymap::{self, Binding, Keymap, Lookup},
+    overlay::OverlayContent,
+    terminal_context::TerminalContext,
+};
 
 #[derive(Debug)]
 pub struct InputState {
+    keymap: Keymap,
     mode: InputMode,
     repeat_count_buffer: String,
+
+    /// The keys typed so far of a sequence like `gg`.
+    pending_keys: Vec<Key>,
+
+    /// When the last key was pressed, for forgetting pending keys that were
+    /// typed too long ago.
+    last_key_time: Instant,
+
     text_buffer: Vec<char>,
     text_cursor: usize,
+
+    /// What the last press of tab could have completed to, when there was
+    /// more than one choice.
+    completions: Vec<String>,
 }
 
 /// Magic School Bus is loosely modal. InputMode is the value that determines
 /// what keys map to what actions.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum InputMode {
     /// The mode from which most commands are started.
     Normal,
@@ -20,9 +42,31 @@ pub enum InputMode {
     /// The user is entering a search string to find files.
     FindPrompt,
 
-    /// The user is being prompted to delete one or more entries.
+    /// The user is typing a filter that narrows the listing down as they type.
+    FilterPrompt,
+
+    /// The user is being prompted to move one or more entries to the trash.
+    TrashPrompt,
+
+    /// The user is being prompted to permanently delete one or more entries.
     DeletePrompt,
 
+    /// The user is browsing the trash to restore entries from it.
+    TrashBrowser,
+
+    /// The user is typing into the fuzzy finder to find a file anywhere under
+    /// the current directory.
+    FinderPrompt,
+
+    /// The user is looking through the results of `:grep`.
+    GrepResults,
+
+    /// The user is reading what a command run with `:!` printed.
+    CommandOutput,
+
+    /// The user is looking through the list of bookmarks.
+    BookmarkList,
+
     /// The user is entering a name for a new file.
     NewFilePrompt,
 
@@ -31,15 +75,34 @@ pub enum InputMode {
 
     /// The user is entering a command to run.
     CommandPrompt,
+
+    /// The user is entering a new name, or a renaming pattern, for the
+    /// selected entries.
+    RenamePrompt,
+
+    /// The new name given in `RenamePrompt` is already taken, and the user is
+    /// being asked whether to overwrite it.
+    RenameOverwritePrompt,
+
+    /// The user is selecting a contiguous range of entries to operate on.
+    Visual,
+
+    /// Entries being pasted collide with existing entries, and the user is
+    /// being asked how to resolve that.
+    PasteConflictPrompt,
 }
 
 impl InputState {
-    pub fn new() -> InputState {
+    pub fn new(keymap: Keymap) -> InputState {
         InputState {
+            keymap,
             mode: InputMode::Normal,
             repeat_count_buffer: String::new(),
+            pending_keys: Vec::new(),
+            last_key_time: Instant::now(),
             text_buffer: Vec::new(),
             text_cursor: 0,
+            completions: Vec::new(),
         }
     }
 
@@ -55,6 +118,12 @@ impl InputState {
         }
     }
 
+    /// The keys typed so far of a sequence that isn't finished yet, like `g`
+    /// before a second `g`.
+    pub fn get_pending_keys(&self) -> String {
+        self.pending_keys.iter().map(keymap::describe_key).collect()
+    }
+
     pub fn get_cursor_position(&self) -> usize {
         self.text_cursor
     }
@@ -63,6 +132,10 @@ impl InputState {
         &self.text_buffer
     }
 
+    pub fn get_completions(&self) -> &[String] {
+        &self.completions
+    }
+
     fn consume_repeat_count(&mut self) -> u64 {
         let count = self.repeat_count_buffer.parse::<u64>().unwrap_or(1);
         self.repeat_count_buffer.clear();
@@ -70,86 +143,353 @@ impl InputState {
         count
     }
 
+    /// Edits the text in a prompt, with the usual readline keys: Ctrl-A and
+    /// Ctrl-E (or Home and End) go to the start and end, Ctrl-B and Ctrl-F
+    /// move like the arrow keys, Ctrl-D deletes forward, Ctrl-W deletes a word
+    /// backward, and Ctrl-U and Ctrl-K cut to the start or end.
+    ///
+    /// Delete and Alt-B/Alt-F can't be used yet: all_term has no key for
+    /// Delete, and Alt arrives as a separate Escape, which would close the
+    /// prompt.
     fn handle_text_key(&mut self, key: Key) {
         match key {
-            Key::Backspace => {
-                if self.text_cursor > 0 {
-                    self.text_buffer.remove(self.text_cursor - 1);
-                    self.text_cursor -= 1;
-                }
+            // Some terminals send DEL or Ctrl-H for backspace.
+            Key::Backspace | Key::Char('\u{7f}') | Key::Char('\u{8}') if self.text_cursor > 0 => {
+                self.text_buffer.remove(self.text_cursor - 1);
+                self.text_cursor -= 1;
+            }
+            // Ctrl-D
+            Key::Char('\u{4}') if self.text_cursor < self.text_buffer.len() => {
+                self.text_buffer.remove(self.text_cursor);
+            }
+            // Ctrl-A and Ctrl-E
+            Key::Home | Key::Char('\u{1}') => self.text_cursor = 0,
+            Key::End | Key::Char('\u{5}') => self.text_cursor = self.text_buffer.len(),
+            // Ctrl-W
+            Key::Char('\u{17}') => {
+                let start = self.word_start();
+                self.text_buffer.drain(start..self.text_cursor);
+                self.text_cursor = start;
+            }
+            // Ctrl-U and Ctrl-K
+            Key::Char('\u{15}') => {
+                self.text_buffer.drain(..self.text_cursor);
+                self.text_cursor = 0;
+            }
+            Key::Char('\u{b}') => {
+                self.text_buffer.truncate(self.text_cursor);
             }
+            Key::Left | Key::Char('\u{2}') if self.text_cursor > 0 => {
+                self.text_cursor -= 1;
+            }
+            Key::Right | Key::Char('\u{6}') if self.text_cursor < self.text_buffer.len() => {
+                self.text_cursor += 1;
+            }
+            // Other control characters would be invisible in the prompt, but
+            // could still sneak in with pasted text.
+            Key::Char(char) if char.is_control() => {}
             Key::Char(char) => {
                 self.text_buffer.insert(self.text_cursor, char);
                 self.text_cursor += 1;
             }
-            Key::Left => {
-                if self.text_cursor > 0 {
-                    self.text_cursor -= 1;
+            _ => {}
+        }
+    }
+
+    /// Where the word before the text cursor starts, skipping any whitespace
+    /// right before the cursor first, like Ctrl-W in a shell.
+    fn word_start(&self) -> usize {
+        let before = &self.text_buffer[..self.text_cursor];
+        let word_end = before
+            .iter()
+            .rposition(|char| !char.is_whitespace())
+            .map_or(0, |index| index + 1);
+
+        before[..word_end]
+            .iter()
+            .rposition(|char| char.is_whitespace())
+            .map_or(0, |index| index + 1)
+    }
+
+    /// Completes the word before the text cursor in prompts that take
+    /// commands or paths.
+    fn complete(&mut self, state: &AppState) {
+        let before: String = self.text_buffer[..self.text_cursor].iter().collect();
+        let directory = &state.working_directory;
+
+        let completion = match self.mode {
+            InputMode::CommandPrompt => completion::complete_command(&before, directory),
+            InputMode::NewDirectoryPrompt => completion::complete_name(&before, directory, true),
+            _ => completion::complete_name(&before, directory, false),
+        };
+
+        if let Some(replacement) = completion.replacement() {
+            let replacement: Vec<char> = replacement.chars().collect();
+            let end = completion.start + replacement.len();
+
+            self.text_buffer
+                .splice(completion.start..self.text_cursor, replacement);
+            self.text_cursor = end;
+        }
+
+        if completion.candidates.len() > 1 {
+            self.completions = completion
+                .candidates
+                .into_iter()
+                .map(|candidate| candidate.label)
+                .collect();
+        }
+    }
+
+    fn set_text_buffer(&mut self, text: &str) {
+        self.text_buffer = text.chars().collect();
+        self.text_cursor = self.text_buffer.len();
+    }
+
+    fn begin_rename(&mut self, state: &AppState) -> Option<Action> {
+        self.repeat_count_buffer.clear();
+
+        let selected = state.selected_entries();
+
+        // Several entries are renamed with a pattern, starting from one that
+        // keeps every name the same.
+        let text = match selected.as_slice() {
+            [] => return None,
+            [entry] => entry.path.file_name()?.to_string_lossy().to_string(),
+            _ => "{}".to_string(),
+        };
+
+        self.set_text_buffer(&text);
+        self.mode = InputMode::RenamePrompt;
+        None
+    }
+
+    fn start_prompt(&mut self, mode: InputMode, text: &str) {
+        self.set_text_buffer(text);
+        self.mode = mode;
+    }
+
+    /// Handles a key in the modes that use the keymap, where keys are either
+    /// part of a count or part of a sequence of keys bound to something.
+    fn process_bound_key(&mut self, key: Key, state: &AppState) -> Option<Action> {
+        if self.pending_keys.is_empty() {
+            if let Key::Char(char @ '0'..='9') = key {
+                self.repeat_count_buffer.push(char);
+                return None;
+            }
+        }
+
+        self.pending_keys.push(key);
+
+        match self.keymap.lookup(self.mode, &self.pending_keys) {
+            Lookup::Pending => None,
+            Lookup::Bound(binding, letter) => {
+                self.pending_keys.clear();
+                self.run_binding(binding, letter, state)
+            }
+            Lookup::Unbound => {
+                let mid_sequence = self.pending_keys.len() > 1;
+                self.pending_keys.clear();
+                self.repeat_count_buffer.clear();
+
+                // Mistyping in the middle of a sequence or outside of normal
+                // mode isn't worth pointing out.
+                if self.mode == InputMode::Normal && !mid_sequence {
+                    Some(Action::Unknown(key))
+                } else {
+                    None
                 }
             }
-            Key::Right => {
-                if self.text_cursor < self.text_buffer.len() {
-                    self.text_cursor += 1;
+        }
+    }
+
+    /// Carries out what a key is bound to in the current mode. `letter` is the
+    /// letter typed after bindings that take one.
+    fn run_binding(
+        &mut self,
+        binding: Binding,
+        letter: Option<char>,
+        state: &AppState,
+    ) -> Option<Action> {
+        let visual = self.mode == InputMode::Visual;
+
+        match binding {
+            Binding::Quit => Some(Action::Quit),
+            Binding::Down => Some(Action::Down(self.consume_repeat_count())),
+            Binding::Up => Some(Action::Up(self.consume_repeat_count())),
+            Binding::Top => Some(Action::Top),
+            Binding::Bottom => Some(Action::Bottom),
+            Binding::Home => Some(Action::GoHome),
+            Binding::Find => {
+                self.start_prompt(InputMode::FindPrompt, "");
+                Some(Action::Find(String::new()))
+            }
+            Binding::Filter => {
+                self.start_prompt(InputMode::FilterPrompt, &state.filter);
+                None
+            }
+            Binding::FindNext => Some(Action::FindNext),
+            Binding::FindPrevious => Some(Action::FindPrevious),
+            Binding::NewFile => {
+                self.start_prompt(InputMode::NewFilePrompt, "");
+                None
+            }
+            Binding::NewDirectory => {
+                self.start_prompt(InputMode::NewDirectoryPrompt, "");
+                None
+            }
+            Binding::Command => {
+                self.start_prompt(InputMode::CommandPrompt, "");
+                None
+            }
+            Binding::Shell => {
+                self.start_prompt(InputMode::CommandPrompt, "!");
+                None
+            }
+            Binding::Rename => self.begin_rename(state),
+            Binding::Visual => {
+                if visual {
+                    self.mode = InputMode::Normal;
+                    Some(Action::Cancel)
+                } else {
+                    self.mode = InputMode::Visual;
+                    Some(Action::StartVisual)
                 }
             }
-            _ => {}
+            Binding::ToggleMark => {
+                self.mode = InputMode::Normal;
+                Some(Action::ToggleSelection)
+            }
+            Binding::InvertMarks => Some(Action::InvertSelection),
+            Binding::ClearMarks => Some(Action::ClearSelection),
+            Binding::Refresh => Some(Action::Refresh),
+            Binding::Open => Some(Action::Activate),
+            Binding::Edit => Some(Action::Edit),
+            Binding::View => Some(Action::View),
+            Binding::Trash => {
+                self.repeat_count_buffer.clear();
+                self.mode = InputMode::TrashPrompt;
+                None
+            }
+            Binding::Delete => {
+                self.repeat_count_buffer.clear();
+                self.mode = InputMode::DeletePrompt;
+                None
+            }
+            Binding::Yank => {
+                self.mode = InputMode::Normal;
+                Some(Action::Yank(self.consume_repeat_count()))
+            }
+            Binding::Cut => {
+                self.mode = InputMode::Normal;
+                Some(Action::Cut(self.consume_repeat_count()))
+            }
+            Binding::Paste => {
+                if state.paste_would_conflict() {
+                    self.repeat_count_buffer.clear();
+                    self.mode = InputMode::PasteConflictPrompt;
+                    None
+                } else {
+                    Some(Action::Paste(ConflictResolution::Skip))
+                }
+            }
+            Binding::OpenTrash => {
+                self.mode = InputMode::TrashBrowser;
+                Some(Action::OpenTrash)
+            }
+            Binding::Finder => {
+                self.start_prompt(InputMode::FinderPrompt, "");
+                Some(Action::OpenFinder)
+            }
+            Binding::HistoryBack => Some(Action::HistoryBack),
+            Binding::HistoryForward => Some(Action::HistoryForward),
+            Binding::Undo => Some(Action::Undo),
+            Binding::Redo => Some(Action::Redo),
+            Binding::Repeat => Some(Action::Repeat(self.consume_repeat_count())),
+            Binding::Bookmark => letter
+                .filter(char::is_ascii_alphabetic)
+                .map(Action::SetBookmark),
+            Binding::GoToBookmark => letter
+                .filter(char::is_ascii_alphabetic)
+                .map(Action::JumpToBookmark),
+            Binding::SortBy(key) => Some(Action::SortBy(key)),
+            Binding::ToggleReverse => Some(Action::ToggleSortReverse),
+            Binding::ToggleDirectoriesFirst => Some(Action::ToggleDirectoriesFirst),
+            Binding::ToggleHidden => Some(Action::ToggleHidden),
+            Binding::CycleFindMode => Some(Action::CycleFindMode),
+            Binding::Restore => Some(Action::RestoreFromTrash),
+            Binding::JumpToBookmark => state.selected_bookmark().map(Action::JumpToBookmark),
+            Binding::DeleteBookmark => state.selected_bookmark().map(Action::DeleteBookmark),
+            Binding::OpenResult => Some(Action::OpenGrepResult),
+            Binding::EditResult => Some(Action::EditGrepResult),
+            Binding::Close => {
+                self.mode = InputMode::Normal;
+                Some(Action::Cancel)
+            }
+            Binding::DebugDump => Some(Action::DebugDumpVisible),
         }
     }
 
-    fn process_input_internal(&mut self, context: &mut TerminalContext) -> Option<Action> {
-        let key = context.read_key();
+    fn process_key(&mut self, key: Key, state: &AppState) -> Option<Action> {
+        self.completions.clear();
+
+        // Waiting for a key gives up once the timeout passes, but where it
+        // can't, a key typed after it still starts a new sequence.
+        let now = Instant::now();
+        let timed_out = self
+            .keymap
+            .timeout
+            .is_some_and(|timeout| now.duration_since(self.last_key_time) > timeout);
+        self.last_key_time = now;
+
+        if !self.pending_keys.is_empty() && (timed_out || key == Key::Escape) {
+            self.pending_keys.clear();
+            self.repeat_count_buffer.clear();
+
+            if key == Key::Escape {
+                return None;
+            }
+        }
 
         if key == Key::Escape {
+            // Escaping out of the filter prompt, or escaping once more after
+            // keeping a filter, brings back the whole listing.
+            let clears_filter = self.mode == InputMode::FilterPrompt
+                || (self.mode == InputMode::Normal && !state.filter.is_empty());
+
             self.mode = InputMode::Normal;
+
+            if clears_filter {
+                return Some(Action::Filter(String::new()));
+            }
+
             return Some(Action::Cancel);
         }
 
         match self.mode {
-            InputMode::Normal => match key {
-                Key::Char('q') => Some(Action::Quit),
-                Key::Char(char @ '0'...'9') => {
-                    self.repeat_count_buffer.push(char);
-                    None
-                }
-                Key::Char('f') | Key::Char('/') => {
-                    self.text_cursor = 0;
-                    self.text_buffer.clear();
-                    self.mode = InputMode::FindPrompt;
+            InputMode::Normal
+            | InputMode::Visual
+            | InputMode::TrashBrowser
+            | InputMode::BookmarkList
+            | InputMode::GrepResults
+            | InputMode::CommandOutput => self.process_bound_key(key, state),
+            InputMode::PasteConflictPrompt => {
+                let resolution = match key {
+                    Key::Char('o') => ConflictResolution::Overwrite,
+                    Key::Char('s') => ConflictResolution::Skip,
+                    Key::Char('r') => ConflictResolution::AutoRename,
+                    _ => return None,
+                };
 
-                    Some(Action::Find(String::new()))
-                }
-                Key::Char('n') => {
-                    self.text_cursor = 0;
-                    self.text_buffer.clear();
-                    self.mode = InputMode::NewFilePrompt;
-                    None
-                }
-                Key::Char('N') => {
-                    self.text_cursor = 0;
-                    self.text_buffer.clear();
-                    self.mode = InputMode::NewDirectoryPrompt;
-                    None
-                }
-                Key::Char(':') => {
-                    self.text_cursor = 0;
-                    self.text_buffer.clear();
-                    self.mode = InputMode::CommandPrompt;
-                    None
-                }
-                Key::Char('j') | Key::Down => Some(Action::Down(self.consume_repeat_count())),
-                Key::Char('k') | Key::Up => Some(Action::Up(self.consume_repeat_count())),
-                Key::Char('g') => Some(Action::Top),
-                Key::Char('G') => Some(Action::Bottom),
-                Key::Char('r') => Some(Action::Refresh),
-                Key::Char('x') => {
-                    self.repeat_count_buffer.clear();
-                    self.mode = InputMode::DeletePrompt;
-                    None
+                self.mode = InputMode::Normal;
+                Some(Action::Paste(resolution))
+            }
+            InputMode::TrashPrompt => match key {
+                Key::Char('y') => {
+                    self.mode = InputMode::Normal;
+                    Some(Action::Trash)
                 }
-                Key::Char('\n') => Some(Action::Activate),
-
-                Key::Char('[') => Some(Action::DebugDumpVisible),
-                _ => Some(Action::Unknown(key)),
+                _ => None,
             },
             InputMode::DeletePrompt => match key {
                 Key::Char('y') => {
@@ -164,6 +504,10 @@ impl InputState {
                     None
                 }
                 Key::Char('\t') => Some(Action::FindNext),
+                // Ctrl-P, since terminals don't tell us about shift-tab
+                Key::Char('\u{10}') => Some(Action::FindPrevious),
+                // Ctrl-T
+                Key::Char('\u{14}') => Some(Action::CycleFindMode),
                 _ => {
                     self.handle_text_key(key);
                     let text: String = self.text_buffer.iter().collect();
@@ -171,6 +515,31 @@ impl InputState {
                     Some(Action::Find(text))
                 }
             },
+            InputMode::FilterPrompt => match key {
+                Key::Char('\n') => {
+                    self.mode = InputMode::Normal;
+                    None
+                }
+                Key::Char('\u{14}') => Some(Action::CycleFindMode),
+                _ => {
+                    self.handle_text_key(key);
+                    let text: String = self.text_buffer.iter().collect();
+
+                    Some(Action::Filter(text))
+                }
+            },
+            InputMode::FinderPrompt => match key {
+                Key::Char('\n') => Some(Action::OpenFinderResult),
+                // Ctrl-N and Ctrl-P, like fzf
+                Key::Up | Key::Char('\u{10}') => Some(Action::Up(1)),
+                Key::Down | Key::Char('\u{e}') => Some(Action::Down(1)),
+                _ => {
+                    self.handle_text_key(key);
+                    let text: String = self.text_buffer.iter().collect();
+
+                    Some(Action::FinderQuery(text))
+                }
+            },
             InputMode::CommandPrompt => match key {
                 Key::Char('\n') => {
                     let text: String = self.text_buffer.iter().collect();
@@ -178,6 +547,10 @@ impl InputState {
 
                     Some(Action::RunCommand(text))
                 }
+                Key::Char('\t') => {
+                    self.complete(state);
+                    None
+                }
                 _ => {
                     self.handle_text_key(key);
                     None
@@ -190,11 +563,52 @@ impl InputState {
 
                     Some(Action::CreateFile(text))
                 }
+                Key::Char('\t') => {
+                    self.complete(state);
+                    None
+                }
+                _ => {
+                    self.handle_text_key(key);
+                    None
+                }
+            },
+            InputMode::RenamePrompt => match key {
+                Key::Char('\n') => {
+                    let text: String = self.text_buffer.iter().collect();
+
+                    if state.rename_would_overwrite(&text) {
+                        self.mode = InputMode::RenameOverwritePrompt;
+                        return None;
+                    }
+
+                    self.mode = InputMode::Normal;
+
+                    Some(Action::Rename {
+                        pattern: text,
+                        overwrite: false,
+                    })
+                }
+                Key::Char('\t') => {
+                    self.complete(state);
+                    None
+                }
                 _ => {
                     self.handle_text_key(key);
                     None
                 }
             },
+            InputMode::RenameOverwritePrompt => match key {
+                Key::Char('y') => {
+                    let text: String = self.text_buffer.iter().collect();
+                    self.mode = InputMode::Normal;
+
+                    Some(Action::Rename {
+                        pattern: text,
+                        overwrite: true,
+                    })
+                }
+                _ => None,
+            },
             InputMode::NewDirectoryPrompt => match key {
                 Key::Char('\n') => {
                     let text: String = self.text_buffer.iter().collect();
@@ -202,6 +616,10 @@ impl InputState {
 
                     Some(Action::CreateDirectory(text))
                 }
+                Key::Char('\t') => {
+                    self.complete(state);
+                    None
+                }
                 _ => {
                     self.handle_text_key(key);
                     None
@@ -210,8 +628,74 @@ impl InputState {
         }
     }
 
-    pub fn process_input(&mut self, context: &mut TerminalContext) -> Option<Action> {
-        let action = self.process_input_internal(context);
+    /// Keeps the mode in line with overlays that the app state opened or
+    /// closed, like the bookmark list opened by a command.
+    pub fn sync_mode(&mut self, state: &AppState) {
+        let overlay_mode = state.overlay.as_ref().map(|overlay| match overlay.content {
+            OverlayContent::Trash(_) => InputMode::TrashBrowser,
+            OverlayContent::Bookmarks(_) => InputMode::BookmarkList,
+            OverlayContent::Finder { .. } => InputMode::FinderPrompt,
+            OverlayContent::Grep { .. } => InputMode::GrepResults,
+            OverlayContent::Output { .. } => InputMode::CommandOutput,
+        });
+
+        match overlay_mode {
+            Some(mode) => {
+                if self.mode == InputMode::Normal {
+                    self.mode = mode;
+                }
+            }
+            None => {
+                if self.mode == InputMode::TrashBrowser
+                    || self.mode == InputMode::BookmarkList
+                    || self.mode == InputMode::FinderPrompt
+                    || self.mode == InputMode::GrepResults
+                    || self.mode == InputMode::CommandOutput
+                {
+                    self.mode = InputMode::Normal;
+                }
+            }
+        }
+    }
+
+    /// How much longer the keys of an unfinished sequence are kept, if there
+    /// are any and there's a limit.
+    fn sequence_timeout(&self) -> Option<Duration> {
+        if self.pending_keys.is_empty() {
+            return None;
+        }
+
+        let timeout = self.keymap.timeout?;
+        Some(timeout.saturating_sub(self.last_key_time.elapsed()))
+    }
+
+    /// Waits for a key and turns it into an action. Gives up once `timeout`
+    /// has passed without a key.
+    pub fn process_input(
+        &mut self,
+        context: &mut TerminalContext,
+        state: &AppState,
+        timeout: Option<Duration>,
+    ) -> Option<Action> {
+        let sequence_timeout = self.sequence_timeout();
+        let wait = match (timeout, sequence_timeout) {
+            (Some(timeout), Some(sequence_timeout)) => Some(timeout.min(sequence_timeout)),
+            (timeout, sequence_timeout) => timeout.or(sequence_timeout),
+        };
+
+        let key = match context.read_key(wait) {
+            Some(key) => key,
+            None => {
+                if self.sequence_timeout() == Some(Duration::ZERO) {
+                    self.pending_keys.clear();
+                    self.repeat_count_buffer.clear();
+                }
+
+                return None;
+            }
+        };
+
+        let action = self.process_key(key, state);
 
         if action.is_some() {
             self.repeat_count_buffer.clear();
@@ -223,6 +707,113 @@ impl InputState {
 
 impl Default for InputState {
     fn default() -> InputState {
-        InputState::new()
+        InputState::new(Keymap::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{file_ops::TempDirectory, settings::Settings};
+
+    fn prompt_with(text: &str) -> InputState {
+        let mut input = InputState::default();
+
+        for char in text.chars() {
+            input.handle_text_key(Key::Char(char));
+        }
+
+        input
+    }
+
+    fn text(input: &InputState) -> String {
+        input.get_text_buffer().iter().collect()
+    }
+
+    #[test]
+    fn text_editing() {
+        let mut input = prompt_with("mv foo bar");
+
+        // Ctrl-A, Ctrl-E, Home, and End
+        input.handle_text_key(Key::Char('\u{1}'));
+        assert_eq!(input.get_cursor_position(), 0);
+        input.handle_text_key(Key::Char('\u{5}'));
+        assert_eq!(input.get_cursor_position(), 10);
+        input.handle_text_key(Key::Home);
+        assert_eq!(input.get_cursor_position(), 0);
+        input.handle_text_key(Key::End);
+        assert_eq!(input.get_cursor_position(), 10);
+
+        // Ctrl-K cuts everything after the cursor.
+        input.handle_text_key(Key::Left);
+        input.handle_text_key(Key::Left);
+        input.handle_text_key(Key::Char('\u{b}'));
+        assert_eq!(text(&input), "mv foo b");
+
+        // Ctrl-U cuts everything before the cursor.
+        input.handle_text_key(Key::Left);
+        input.handle_text_key(Key::Char('\u{15}'));
+        assert_eq!(text(&input), "b");
+        assert_eq!(input.get_cursor_position(), 0);
+
+        // Typing after moving the cursor inserts in the middle.
+        input.handle_text_key(Key::Char('a'));
+        input.handle_text_key(Key::End);
+        input.handle_text_key(Key::Char('c'));
+        assert_eq!(text(&input), "abc");
+    }
+
+    #[test]
+    fn word_deletion() {
+        // Ctrl-W skips the spaces before the cursor, then deletes a word.
+        let mut input = prompt_with("mv foo  ");
+        input.handle_text_key(Key::Char('\u{17}'));
+        assert_eq!(text(&input), "mv ");
+        input.handle_text_key(Key::Char('\u{17}'));
+        assert_eq!(text(&input), "");
+        input.handle_text_key(Key::Char('\u{17}'));
+        assert_eq!(text(&input), "");
+
+        // Words are made of characters, not bytes.
+        let mut input = prompt_with("日本 語の 名前");
+        input.handle_text_key(Key::Left);
+        assert_eq!(input.word_start(), 6);
+        input.handle_text_key(Key::Char('\u{17}'));
+        assert_eq!(text(&input), "日本 語の 前");
+        assert_eq!(input.get_cursor_position(), 6);
+
+        input.handle_text_key(Key::Char('\u{17}'));
+        assert_eq!(text(&input), "日本 前");
+        assert_eq!(input.get_cursor_position(), 3);
+    }
+
+    #[test]
+    fn escape_clears_filter() {
+        let directory = TempDirectory::new("escape-clears-filter");
+        let mut state = AppState::new(directory.path().to_path_buf(), Settings::default());
+        let mut input = InputState::default();
+
+        input.process_key(Key::Char('F'), &state);
+        assert_eq!(input.get_mode(), InputMode::FilterPrompt);
+        assert_eq!(
+            input.process_key(Key::Char('a'), &state),
+            Some(Action::Filter("a".to_string()))
+        );
+        assert_eq!(
+            input.process_key(Key::Escape, &state),
+            Some(Action::Filter(String::new()))
+        );
+        assert_eq!(input.get_mode(), InputMode::Normal);
+
+        // A filter that was kept with return is cleared by the next escape.
+        state.filter = "a".to_string();
+        assert_eq!(
+            input.process_key(Key::Escape, &state),
+            Some(Action::Filter(String::new()))
+        );
+
+        state.filter.clear();
+        assert_eq!(input.process_key(Key::Escape, &state), Some(Action::Cancel));
     }
 }
@@ -0,0 +1,502 @@
+//! Keeps track of changes made to the filesystem so that they can be undone
+//! and redone.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::AppError,
+    file_ops,
+    trash::{Trash, TrashItem},
+};
+
+/// How many operations are remembered before the oldest ones are forgotten.
+const MAX_OPERATIONS: usize = 100;
+
+/// A single reversible change to the filesystem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Create { path: PathBuf, is_directory: bool },
+    Move { from: PathBuf, to: PathBuf },
+    Copy { from: PathBuf, to: PathBuf },
+    Trash { item: TrashItem },
+}
+
+impl Change {
+    /// Checks that undoing the change won't clobber anything that happened to
+    /// the filesystem since the change was made.
+    fn check_undo(&self, trash: Option<&Trash>) -> Result<(), AppError> {
+        let unsafe_undo = |path: &Path, reason| {
+            Err(AppError::UnsafeUndo {
+                path: path.to_path_buf(),
+                reason,
+            })
+        };
+
+        match self {
+            Change::Create { path, is_directory } => {
+                let unchanged = if *is_directory {
+                    fs::read_dir(path)
+                        .map(|mut entries| entries.next().is_none())
+                        .unwrap_or(false)
+                } else {
+                    fs::symlink_metadata(path)
+                        .map(|metadata| metadata.len() == 0)
+                        .unwrap_or(false)
+                };
+
+                if !unchanged {
+                    return unsafe_undo(path, "it has changed since it was created");
+                }
+            }
+            Change::Move { from, to } => {
+                if !file_ops::path_exists(to) {
+                    return unsafe_undo(to, "it doesn't exist anymore");
+                }
+
+                if file_ops::path_exists(from) {
+                    return unsafe_undo(from, "something else is there now");
+                }
+            }
+            Change::Copy { to, .. } => {
+                if !file_ops::path_exists(to) {
+                    return unsafe_undo(to, "it doesn't exist anymore");
+                }
+            }
+            Change::Trash { item } => {
+                if !needs_trash(trash).contains(item) {
+                    return unsafe_undo(&item.original_path, "it isn't in the trash anymore");
+                }
+
+                if file_ops::path_exists(&item.original_path) {
+                    return unsafe_undo(&item.original_path, "something else is there now");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that redoing the change won't clobber anything that happened to
+    /// the filesystem since the change was undone.
+    fn check_redo(&self) -> Result<(), AppError> {
+        let unsafe_redo = |path: &Path, reason| {
+            Err(AppError::UnsafeUndo {
+                path: path.to_path_buf(),
+                reason,
+            })
+        };
+
+        match self {
+            Change::Create { path, .. } => {
+                if file_ops::path_exists(path) {
+                    return unsafe_redo(path, "something else is there now");
+                }
+            }
+            Change::Move { from, to } | Change::Copy { from, to } => {
+                if !file_ops::path_exists(from) {
+                    return unsafe_redo(from, "it doesn't exist anymore");
+                }
+
+                if file_ops::path_exists(to) {
+                    return unsafe_redo(to, "something else is there now");
+                }
+            }
+            Change::Trash { item } => {
+                if !file_ops::path_exists(&item.original_path) {
+                    return unsafe_redo(&item.original_path, "it doesn't exist anymore");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reverses the change, returning the change that would redo it.
+    fn undo(&self, trash: Option<&Trash>) -> io::Result<Change> {
+        match self {
+            Change::Create { path, is_directory } => {
+                if *is_directory {
+                    fs::remove_dir(path)?;
+                } else {
+                    fs::remove_file(path)?;
+                }
+            }
+            Change::Move { from, to } => file_ops::move_path(to, from)?,
+            Change::Copy { to, .. } => {
+                // The copy might have been changed since it was made, so it's
+                // safer to put it in the trash than to delete it.
+                needs_trash(trash).put(to)?;
+            }
+            Change::Trash { item } => needs_trash(trash).restore(item)?,
+        }
+
+        Ok(self.clone())
+    }
+
+    /// Makes the change again, returning the change that would undo it.
+    fn redo(&self, trash: Option<&Trash>) -> io::Result<Change> {
+        match self {
+            Change::Create { path, is_directory } => {
+                if *is_directory {
+                    fs::create_dir(path)?;
+                } else {
+                    fs::File::create(path)?;
+                }
+            }
+            Change::Move { from, to } => file_ops::move_path(from, to)?,
+            Change::Copy { from, to } => file_ops::copy_recursive(from, to)?,
+            Change::Trash { item } => {
+                // Trashing again can put the entry under a different name in
+                // the trash, so the change needs to be updated to match.
+                let item = needs_trash(trash).put(&item.original_path)?;
+                return Ok(Change::Trash { item });
+            }
+        }
+
+        Ok(self.clone())
+    }
+
+    /// The path that this change is most about, used for error messages.
+    fn path(&self) -> &Path {
+        match self {
+            Change::Create { path, .. } => path,
+            Change::Move { to, .. } | Change::Copy { to, .. } => to,
+            Change::Trash { item } => &item.original_path,
+        }
+    }
+
+    /// Whether undoing or redoing this change can put entries into the trash
+    /// or take them out of it.
+    fn uses_trash(&self) -> bool {
+        matches!(self, Change::Copy { .. } | Change::Trash { .. })
+    }
+}
+
+/// The trash for a change that uses one. `Journal::apply` checks that there's
+/// a trash before touching any change that needs it.
+fn needs_trash(trash: Option<&Trash>) -> &Trash {
+    trash.expect("changes that use the trash need a trash")
+}
+
+/// A group of changes made by one action, undone and redone together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Operation {
+    pub changes: Vec<Change>,
+}
+
+#[derive(Debug, Default)]
+pub struct Journal {
+    undo_stack: Vec<Operation>,
+    redo_stack: Vec<Operation>,
+}
+
+impl Journal {
+    pub fn new() -> Journal {
+        Journal::default()
+    }
+
+    /// Remembers the changes made by an action. Making a new change means
+    /// anything that was undone can't be redone anymore.
+    pub fn record(&mut self, changes: Vec<Change>) {
+        if changes.is_empty() {
+            return;
+        }
+
+        self.undo_stack.push(Operation { changes });
+        self.redo_stack.clear();
+
+        if self.undo_stack.len() > MAX_OPERATIONS {
+            self.undo_stack.remove(0);
+        }
+    }
+
+    /// Undoes the last operation. The trash is only needed for operations
+    /// that copied or trashed entries.
+    pub fn undo(&mut self, trash: Option<&Trash>) -> Result<(), AppError> {
+        let operation = self.undo_stack.pop().ok_or(AppError::NothingToUndo)?;
+
+        match Journal::apply(&operation, trash, Direction::Undo) {
+            Ok(operation) => {
+                self.redo_stack.push(operation);
+                Ok(())
+            }
+            Err((operation, error)) => {
+                self.undo_stack.push(operation);
+                Err(error)
+            }
+        }
+    }
+
+    pub fn redo(&mut self, trash: Option<&Trash>) -> Result<(), AppError> {
+        let operation = self.redo_stack.pop().ok_or(AppError::NothingToRedo)?;
+
+        match Journal::apply(&operation, trash, Direction::Redo) {
+            Ok(operation) => {
+                self.undo_stack.push(operation);
+                Ok(())
+            }
+            Err((operation, error)) => {
+                self.redo_stack.push(operation);
+                Err(error)
+            }
+        }
+    }
+
+    /// Undoes or redoes every change in an operation. If any change can't be
+    /// made safely, the changes that were already made are rolled back so the
+    /// operation is either applied completely or not at all, and the operation
+    /// to keep in its place is returned along with the error.
+    fn apply(
+        operation: &Operation,
+        trash: Option<&Trash>,
+        direction: Direction,
+    ) -> Result<Operation, (Operation, AppError)> {
+        if trash.is_none() && operation.changes.iter().any(Change::uses_trash) {
+            return Err((operation.clone(), AppError::NoTrash));
+        }
+
+        let mut changes: Vec<Change> = operation.changes.clone();
+
+        if direction == Direction::Undo {
+            changes.reverse();
+        }
+
+        let mut applied = Vec::new();
+
+        for change in &changes {
+            let result = match direction {
+                Direction::Undo => change.check_undo(trash).and_then(|_| {
+                    change.undo(trash).map_err(|source| AppError::Undo {
+                        path: change.path().to_path_buf(),
+                        source,
+                    })
+                }),
+                Direction::Redo => change.check_redo().and_then(|_| {
+                    change.redo(trash).map_err(|source| AppError::Undo {
+                        path: change.path().to_path_buf(),
+                        source,
+                    })
+                }),
+            };
+
+            match result {
+                Ok(reversed) => applied.push(reversed),
+                Err(error) => {
+                    // Rolling a change back can alter it, like an entry going
+                    // back into the trash under a new name, so the operation
+                    // is rebuilt from how its changes were rolled back.
+                    let mut rolled_back = changes.clone();
+
+                    for (index, reversed) in applied.iter().enumerate().rev() {
+                        let result = match direction {
+                            Direction::Undo => reversed.redo(trash),
+                            Direction::Redo => reversed.undo(trash),
+                        };
+
+                        if let Ok(change) = result {
+                            rolled_back[index] = change;
+                        }
+                    }
+
+                    if direction == Direction::Undo {
+                        rolled_back.reverse();
+                    }
+
+                    return Err((
+                        Operation {
+                            changes: rolled_back,
+                        },
+                        error,
+                    ));
+                }
+            }
+        }
+
+        if direction == Direction::Undo {
+            applied.reverse();
+        }
+
+        Ok(Operation { changes: applied })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Undo,
+    Redo,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::file_ops::TempDirectory;
+
+    #[test]
+    fn rename_undo_redo() {
+        let directory = TempDirectory::new("journal-rename");
+        let trash = Trash::at(&directory.path().join("trash"));
+        let from = directory.path().join("from");
+        let to = directory.path().join("to");
+
+        fs::write(&from, "contents").unwrap();
+        fs::rename(&from, &to).unwrap();
+
+        let mut journal = Journal::new();
+        journal.record(vec![Change::Move {
+            from: from.clone(),
+            to: to.clone(),
+        }]);
+
+        journal.undo(Some(&trash)).unwrap();
+        assert_eq!(fs::read_to_string(&from).unwrap(), "contents");
+        assert!(!to.exists());
+
+        journal.redo(Some(&trash)).unwrap();
+        assert_eq!(fs::read_to_string(&to).unwrap(), "contents");
+        assert!(!from.exists());
+
+        journal.undo(Some(&trash)).unwrap();
+        assert!(from.exists());
+
+        match journal.undo(Some(&trash)) {
+            Err(AppError::NothingToUndo) => {}
+            other => panic!("expected nothing to undo, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn undo_without_trash() {
+        let directory = TempDirectory::new("journal-no-trash");
+        let from = directory.path().join("from");
+        let to = directory.path().join("to");
+
+        fs::write(&from, "contents").unwrap();
+        fs::copy(&from, &to).unwrap();
+
+        let mut journal = Journal::new();
+        journal.record(vec![Change::Copy {
+            from: from.clone(),
+            to: to.clone(),
+        }]);
+
+        // Undoing a copy puts the copy in the trash, so it needs one.
+        match journal.undo(None) {
+            Err(AppError::NoTrash) => {}
+            other => panic!("expected no trash, got {:?}", other),
+        }
+        assert!(to.exists());
+
+        fs::remove_file(&to).unwrap();
+        fs::rename(&from, &to).unwrap();
+
+        journal.record(vec![Change::Move {
+            from: from.clone(),
+            to: to.clone(),
+        }]);
+
+        journal.undo(None).unwrap();
+        assert!(from.exists());
+        assert!(!to.exists());
+    }
+
+    #[test]
+    fn create_undo() {
+        let directory = TempDirectory::new("journal-create");
+        let trash = Trash::at(&directory.path().join("trash"));
+        let path = directory.path().join("created");
+
+        fs::File::create(&path).unwrap();
+
+        let mut journal = Journal::new();
+        journal.record(vec![Change::Create {
+            path: path.clone(),
+            is_directory: false,
+        }]);
+
+        // Something was written to the file, so removing it would lose that.
+        fs::write(&path, "changed").unwrap();
+        assert!(journal.undo(Some(&trash)).is_err());
+        assert!(path.exists());
+
+        fs::write(&path, "").unwrap();
+        journal.undo(Some(&trash)).unwrap();
+        assert!(!path.exists());
+
+        journal.redo(Some(&trash)).unwrap();
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn trash_undo() {
+        let directory = TempDirectory::new("journal-trash");
+        let trash = Trash::at(&directory.path().join("trash"));
+        let path = directory.path().join("trashed");
+
+        fs::write(&path, "contents").unwrap();
+        let item = trash.put(&path).unwrap();
+        assert!(!path.exists());
+
+        let mut journal = Journal::new();
+        journal.record(vec![Change::Trash { item }]);
+
+        journal.undo(Some(&trash)).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "contents");
+        assert!(trash.list().unwrap().is_empty());
+
+        journal.redo(Some(&trash)).unwrap();
+        assert!(!path.exists());
+        assert_eq!(trash.list().unwrap().len(), 1);
+
+        journal.undo(Some(&trash)).unwrap();
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn failed_undo_rolls_back() {
+        let directory = TempDirectory::new("journal-rollback");
+        let trash = Trash::at(&directory.path().join("trash"));
+        let from = directory.path().join("from");
+        let to = directory.path().join("to");
+        let trashed = directory.path().join("trashed");
+
+        fs::write(&from, "moved").unwrap();
+        fs::write(&trashed, "trashed").unwrap();
+        fs::rename(&from, &to).unwrap();
+        let item = trash.put(&trashed).unwrap();
+
+        let mut journal = Journal::new();
+        journal.record(vec![
+            Change::Move {
+                from: from.clone(),
+                to: to.clone(),
+            },
+            Change::Trash { item },
+        ]);
+
+        // Undoing restores the trashed entry first, then can't move `to`
+        // back, so the restored entry has to go back into the trash.
+        fs::write(&from, "in the way").unwrap();
+
+        match journal.undo(Some(&trash)) {
+            Err(AppError::UnsafeUndo { path, .. }) => assert_eq!(path, from),
+            other => panic!("expected an unsafe undo, got {:?}", other),
+        }
+
+        assert!(!trashed.exists());
+        assert_eq!(fs::read_to_string(&to).unwrap(), "moved");
+        assert_eq!(fs::read_to_string(&from).unwrap(), "in the way");
+        assert_eq!(journal.undo_stack.len(), 1);
+        assert!(journal.redo_stack.is_empty());
+
+        fs::remove_file(&from).unwrap();
+        journal.undo(Some(&trash)).unwrap();
+
+        assert_eq!(fs::read_to_string(&from).unwrap(), "moved");
+        assert_eq!(fs::read_to_string(&trashed).unwrap(), "trashed");
+        assert!(journal.undo_stack.is_empty());
+    }
+}
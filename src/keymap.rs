@@ -0,0 +1,683 @@
+//! Which keys do what in the modes where keys run actions, like normal mode
+//! and the lists shown in overlays. Actions can be bound to single keys or to
+//! sequences of keys like `gg`, and keys can be changed from the config file.
+
+use std::{collections::HashMap, time::Duration};
+
+use all_term::Key;
+
+use crate::{input_state::InputMode, sort::SortKey};
+
+/// Something a key can be bound to. Most of these turn straight into an
+/// action, while others switch modes or open a prompt first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    Quit,
+    Down,
+    Up,
+    Top,
+    Bottom,
+    Home,
+    Find,
+    Filter,
+    FindNext,
+    FindPrevious,
+    NewFile,
+    NewDirectory,
+    Command,
+    Shell,
+    Rename,
+    Visual,
+    ToggleMark,
+    InvertMarks,
+    ClearMarks,
+    Refresh,
+    Open,
+    Edit,
+    View,
+    Trash,
+    Delete,
+    Yank,
+    Cut,
+    Paste,
+    OpenTrash,
+    Finder,
+    HistoryBack,
+    HistoryForward,
+    Undo,
+    Redo,
+    Repeat,
+    Bookmark,
+    GoToBookmark,
+    SortBy(SortKey),
+    ToggleReverse,
+    ToggleDirectoriesFirst,
+    ToggleHidden,
+    CycleFindMode,
+    Restore,
+    JumpToBookmark,
+    DeleteBookmark,
+    OpenResult,
+    EditResult,
+    Close,
+    DebugDump,
+}
+
+impl Binding {
+    pub const ALL: [Binding; 54] = [
+        Binding::Quit,
+        Binding::Down,
+        Binding::Up,
+        Binding::Top,
+        Binding::Bottom,
+        Binding::Home,
+        Binding::Find,
+        Binding::Filter,
+        Binding::FindNext,
+        Binding::FindPrevious,
+        Binding::NewFile,
+        Binding::NewDirectory,
+        Binding::Command,
+        Binding::Shell,
+        Binding::Rename,
+        Binding::Visual,
+        Binding::ToggleMark,
+        Binding::InvertMarks,
+        Binding::ClearMarks,
+        Binding::Refresh,
+        Binding::Open,
+        Binding::Edit,
+        Binding::View,
+        Binding::Trash,
+        Binding::Delete,
+        Binding::Yank,
+        Binding::Cut,
+        Binding::Paste,
+        Binding::OpenTrash,
+        Binding::Finder,
+        Binding::HistoryBack,
+        Binding::HistoryForward,
+        Binding::Undo,
+        Binding::Redo,
+        Binding::Repeat,
+        Binding::Bookmark,
+        Binding::GoToBookmark,
+        Binding::SortBy(SortKey::Name),
+        Binding::SortBy(SortKey::Natural),
+        Binding::SortBy(SortKey::Size),
+        Binding::SortBy(SortKey::Modified),
+        Binding::SortBy(SortKey::Extension),
+        Binding::SortBy(SortKey::Kind),
+        Binding::ToggleReverse,
+        Binding::ToggleDirectoriesFirst,
+        Binding::ToggleHidden,
+        Binding::CycleFindMode,
+        Binding::Restore,
+        Binding::JumpToBookmark,
+        Binding::DeleteBookmark,
+        Binding::OpenResult,
+        Binding::EditResult,
+        Binding::Close,
+        Binding::DebugDump,
+    ];
+
+    /// The name used for the binding in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Binding::Quit => "quit",
+            Binding::Down => "down",
+            Binding::Up => "up",
+            Binding::Top => "top",
+            Binding::Bottom => "bottom",
+            Binding::Home => "home",
+            Binding::Find => "find",
+            Binding::Filter => "filter",
+            Binding::FindNext => "find-next",
+            Binding::FindPrevious => "find-previous",
+            Binding::NewFile => "new-file",
+            Binding::NewDirectory => "new-directory",
+            Binding::Command => "command",
+            Binding::Shell => "shell",
+            Binding::Rename => "rename",
+            Binding::Visual => "visual",
+            Binding::ToggleMark => "toggle-mark",
+            Binding::InvertMarks => "invert-marks",
+            Binding::ClearMarks => "clear-marks",
+            Binding::Refresh => "refresh",
+            Binding::Open => "open",
+            Binding::Edit => "edit",
+            Binding::View => "view",
+            Binding::Trash => "trash",
+            Binding::Delete => "delete",
+            Binding::Yank => "yank",
+            Binding::Cut => "cut",
+            Binding::Paste => "paste",
+            Binding::OpenTrash => "open-trash",
+            Binding::Finder => "finder",
+            Binding::HistoryBack => "history-back",
+            Binding::HistoryForward => "history-forward",
+            Binding::Undo => "undo",
+            Binding::Redo => "redo",
+            Binding::Repeat => "repeat",
+            Binding::Bookmark => "bookmark",
+            Binding::GoToBookmark => "go-to-bookmark",
+            Binding::SortBy(SortKey::Name) => "sort-name",
+            Binding::SortBy(SortKey::Natural) => "sort-natural",
+            Binding::SortBy(SortKey::Size) => "sort-size",
+            Binding::SortBy(SortKey::Modified) => "sort-mtime",
+            Binding::SortBy(SortKey::Extension) => "sort-extension",
+            Binding::SortBy(SortKey::Kind) => "sort-kind",
+            Binding::ToggleReverse => "toggle-reverse",
+            Binding::ToggleDirectoriesFirst => "toggle-dirsfirst",
+            Binding::ToggleHidden => "toggle-hidden",
+            Binding::CycleFindMode => "cycle-find-mode",
+            Binding::Restore => "restore",
+            Binding::JumpToBookmark => "jump",
+            Binding::DeleteBookmark => "delete-bookmark",
+            Binding::OpenResult => "open-result",
+            Binding::EditResult => "edit-result",
+            Binding::Close => "close",
+            Binding::DebugDump => "debug-dump",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Binding> {
+        Binding::ALL
+            .iter()
+            .cloned()
+            .find(|binding| binding.name() == name)
+    }
+
+    /// Whether the binding is followed by a letter, like the `a` in `ma`.
+    pub fn takes_letter(self) -> bool {
+        matches!(self, Binding::Bookmark | Binding::GoToBookmark)
+    }
+
+    /// Whether the binding means anything in the given mode. Moving the
+    /// cursor works everywhere, but most bindings only make sense in one or
+    /// two modes.
+    pub fn works_in(self, mode: InputMode) -> bool {
+        match self {
+            Binding::Down | Binding::Up | Binding::Top | Binding::Bottom => true,
+            Binding::FindNext
+            | Binding::FindPrevious
+            | Binding::Rename
+            | Binding::Visual
+            | Binding::ToggleMark
+            | Binding::Trash
+            | Binding::Delete
+            | Binding::Yank
+            | Binding::Cut => mode == InputMode::Normal || mode == InputMode::Visual,
+            Binding::Restore => mode == InputMode::TrashBrowser,
+            Binding::JumpToBookmark | Binding::DeleteBookmark => mode == InputMode::BookmarkList,
+            Binding::OpenResult | Binding::EditResult => mode == InputMode::GrepResults,
+            Binding::Close => {
+                mode == InputMode::TrashBrowser
+                    || mode == InputMode::BookmarkList
+                    || mode == InputMode::GrepResults
+                    || mode == InputMode::CommandOutput
+            }
+            _ => mode == InputMode::Normal,
+        }
+    }
+}
+
+/// The key tables in the config file, and the modes they're for.
+pub const MODE_NAMES: [(&str, InputMode); 6] = [
+    ("normal", InputMode::Normal),
+    ("visual", InputMode::Visual),
+    ("trash", InputMode::TrashBrowser),
+    ("bookmarks", InputMode::BookmarkList),
+    ("grep", InputMode::GrepResults),
+    ("output", InputMode::CommandOutput),
+];
+
+/// Where a sequence of keys leads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lookup {
+    /// The keys are bound, and the binding can be run. Bindings that take a
+    /// letter come with the letter typed after them.
+    Bound(Binding, Option<char>),
+
+    /// The keys are the start of at least one binding, so there's more to
+    /// type.
+    Pending,
+
+    /// Nothing is bound to the keys, or to anything starting with them.
+    Unbound,
+}
+
+/// A tree of key sequences, where each key leads on to the keys that can be
+/// typed after it. A sequence is either bound or leads on to longer ones,
+/// never both, so there's no waiting to see whether more keys are coming.
+#[derive(Debug, Clone, Default)]
+struct KeyTree {
+    binding: Option<Binding>,
+
+    /// There are only a few dozen keys bound at any level, so they're looked
+    /// up one by one.
+    next: Vec<(Key, KeyTree)>,
+}
+
+impl KeyTree {
+    fn next(&self, key: &Key) -> Option<&KeyTree> {
+        self.next
+            .iter()
+            .find(|(next_key, _)| next_key == key)
+            .map(|(_, tree)| tree)
+    }
+
+    /// Binds a sequence, replacing anything bound to the start of it or to
+    /// longer sequences starting with it.
+    fn insert(&mut self, keys: &[Key], binding: Binding) {
+        let (key, rest) = match keys.split_first() {
+            Some(split) => split,
+            None => {
+                self.binding = Some(binding);
+                self.next.clear();
+                return;
+            }
+        };
+
+        self.binding = None;
+
+        let index = match self.next.iter().position(|(next_key, _)| next_key == key) {
+            Some(index) => index,
+            None => {
+                self.next.push((*key, KeyTree::default()));
+                self.next.len() - 1
+            }
+        };
+
+        self.next[index].1.insert(rest, binding);
+    }
+
+    /// Unbinds a sequence along with any longer ones starting with it,
+    /// returning whether there's nothing left bound in this tree.
+    fn remove(&mut self, keys: &[Key]) -> bool {
+        match keys.split_first() {
+            Some((key, rest)) => {
+                if let Some(index) = self.next.iter().position(|(next_key, _)| next_key == key) {
+                    if self.next[index].1.remove(rest) {
+                        self.next.remove(index);
+                    }
+                }
+            }
+            None => {
+                self.binding = None;
+                self.next.clear();
+            }
+        }
+
+        self.binding.is_none() && self.next.is_empty()
+    }
+}
+
+/// The key sequences bound in each mode.
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    modes: HashMap<InputMode, KeyTree>,
+
+    /// What `<leader>` means in key sequences.
+    leader: Key,
+
+    /// How long there can be between the keys of a sequence. A key typed any
+    /// later starts a new sequence instead of finishing the last one. `None`
+    /// lets a sequence be finished at any time.
+    pub timeout: Option<Duration>,
+}
+
+impl Keymap {
+    /// A keymap with nothing bound at all.
+    pub fn empty() -> Keymap {
+        Keymap {
+            modes: HashMap::new(),
+            leader: Key::Char('\\'),
+            timeout: Some(Duration::from_millis(1000)),
+        }
+    }
+
+    /// Finds out what a sequence of keys typed in the given mode does.
+    pub fn lookup(&self, mode: InputMode, keys: &[Key]) -> Lookup {
+        let mut tree = match self.modes.get(&mode) {
+            Some(tree) => tree,
+            None => return Lookup::Unbound,
+        };
+
+        for (index, key) in keys.iter().enumerate() {
+            if let Some(binding) = tree.binding {
+                return match &keys[index..] {
+                    [Key::Char(letter)] if binding.takes_letter() => {
+                        Lookup::Bound(binding, Some(*letter))
+                    }
+                    _ => Lookup::Unbound,
+                };
+            }
+
+            tree = match tree.next(key) {
+                Some(tree) => tree,
+                None => return Lookup::Unbound,
+            };
+        }
+
+        match tree.binding {
+            Some(binding) if !binding.takes_letter() => Lookup::Bound(binding, None),
+            _ => Lookup::Pending,
+        }
+    }
+
+    /// Binds a sequence of keys, replacing whatever it was bound to before,
+    /// along with anything bound to the start of it or to longer sequences
+    /// starting with it.
+    pub fn bind(&mut self, mode: InputMode, keys: &[Key], binding: Binding) {
+        self.modes.entry(mode).or_default().insert(keys, binding);
+    }
+
+    /// Unbinds a sequence of keys, along with any longer sequences starting
+    /// with it.
+    pub fn unbind(&mut self, mode: InputMode, keys: &[Key]) {
+        if let Some(tree) = self.modes.get_mut(&mode) {
+            tree.remove(keys);
+        }
+    }
+
+    pub fn set_leader(&mut self, leader: Key) {
+        self.leader = leader;
+    }
+
+    /// Reads a sequence of keys written the way Vim writes them, where
+    /// `<leader>` stands for the leader key.
+    pub fn parse_keys(&self, text: &str) -> Option<Vec<Key>> {
+        parse_keys(text, &self.leader)
+    }
+
+    fn bind_all(&mut self, mode: InputMode, bindings: &[(&str, Binding)]) {
+        for &(keys, binding) in bindings {
+            let keys = self
+                .parse_keys(keys)
+                .unwrap_or_else(|| panic!("Invalid default key sequence {}", keys));
+
+            self.bind(mode, &keys, binding);
+        }
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        let mut keymap = Keymap::empty();
+
+        let movement = [
+            ("j", Binding::Down),
+            ("<down>", Binding::Down),
+            ("k", Binding::Up),
+            ("<up>", Binding::Up),
+            ("gg", Binding::Top),
+            ("G", Binding::Bottom),
+        ];
+
+        for &(_, mode) in &MODE_NAMES {
+            keymap.bind_all(mode, &movement);
+        }
+
+        keymap.bind_all(
+            InputMode::Normal,
+            &[
+                ("q", Binding::Quit),
+                ("gh", Binding::Home),
+                ("f", Binding::Find),
+                ("/", Binding::Find),
+                ("F", Binding::Filter),
+                ("n", Binding::FindNext),
+                ("N", Binding::FindPrevious),
+                ("a", Binding::NewFile),
+                ("A", Binding::NewDirectory),
+                (":", Binding::Command),
+                ("!", Binding::Shell),
+                ("R", Binding::Rename),
+                ("V", Binding::Visual),
+                ("<space>", Binding::ToggleMark),
+                ("*", Binding::InvertMarks),
+                ("c", Binding::ClearMarks),
+                ("r", Binding::Refresh),
+                ("e", Binding::Edit),
+                ("v", Binding::View),
+                ("x", Binding::Trash),
+                ("X", Binding::Delete),
+                ("yy", Binding::Yank),
+                ("dd", Binding::Cut),
+                ("p", Binding::Paste),
+                ("T", Binding::OpenTrash),
+                ("<c-p>", Binding::Finder),
+                // Ctrl-I is the same as tab to terminals.
+                ("<c-o>", Binding::HistoryBack),
+                ("<tab>", Binding::HistoryForward),
+                ("u", Binding::Undo),
+                ("<c-r>", Binding::Redo),
+                (".", Binding::Repeat),
+                ("m", Binding::Bookmark),
+                ("'", Binding::GoToBookmark),
+                ("ob", Binding::SortBy(SortKey::Name)),
+                ("on", Binding::SortBy(SortKey::Natural)),
+                ("os", Binding::SortBy(SortKey::Size)),
+                ("om", Binding::SortBy(SortKey::Modified)),
+                ("oe", Binding::SortBy(SortKey::Extension)),
+                ("ot", Binding::SortBy(SortKey::Kind)),
+                ("or", Binding::ToggleReverse),
+                ("od", Binding::ToggleDirectoriesFirst),
+                ("zh", Binding::ToggleHidden),
+                ("<cr>", Binding::Open),
+                ("[", Binding::DebugDump),
+            ],
+        );
+
+        keymap.bind_all(
+            InputMode::Visual,
+            &[
+                ("n", Binding::FindNext),
+                ("N", Binding::FindPrevious),
+                ("x", Binding::Trash),
+                ("X", Binding::Delete),
+                ("y", Binding::Yank),
+                ("d", Binding::Cut),
+                ("R", Binding::Rename),
+                ("<space>", Binding::ToggleMark),
+                ("V", Binding::Visual),
+            ],
+        );
+
+        keymap.bind_all(
+            InputMode::TrashBrowser,
+            &[
+                ("<cr>", Binding::Restore),
+                ("r", Binding::Restore),
+                ("T", Binding::Close),
+            ],
+        );
+
+        keymap.bind_all(
+            InputMode::BookmarkList,
+            &[
+                ("<cr>", Binding::JumpToBookmark),
+                ("d", Binding::DeleteBookmark),
+                ("x", Binding::DeleteBookmark),
+            ],
+        );
+
+        keymap.bind_all(
+            InputMode::GrepResults,
+            &[("<cr>", Binding::OpenResult), ("e", Binding::EditResult)],
+        );
+
+        keymap.bind_all(
+            InputMode::CommandOutput,
+            &[("q", Binding::Close), ("<cr>", Binding::Close)],
+        );
+
+        keymap
+    }
+}
+
+/// Reads a sequence of keys written the way Vim writes them: characters like
+/// `gg`, mixed with names in angle brackets like `<space>`, `<tab>`, `<cr>`,
+/// `<bs>`, `<up>`, `<home>`, `<leader>`, or `<c-p>` for Ctrl-P. `<lt>` is a
+/// plain `<`, and so is a `<` that doesn't start a name. Function keys have
+/// no names, since the terminal never reports them.
+pub fn parse_keys(text: &str, leader: &Key) -> Option<Vec<Key>> {
+    let mut keys = Vec::new();
+    let mut rest = text;
+
+    while let Some(char) = rest.chars().next() {
+        if char == '<' {
+            if let Some(end) = rest.find('>') {
+                let name = &rest[1..end];
+                let is_name = !name.is_empty()
+                    && name
+                        .chars()
+                        .all(|char| char.is_ascii_alphanumeric() || char == '-');
+
+                if is_name {
+                    let key = if name.eq_ignore_ascii_case("leader") {
+                        *leader
+                    } else {
+                        parse_key_name(name)?
+                    };
+
+                    keys.push(key);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+        }
+
+        keys.push(Key::Char(char));
+        rest = &rest[char.len_utf8()..];
+    }
+
+    if keys.is_empty() {
+        None
+    } else {
+        Some(keys)
+    }
+}
+
+fn parse_key_name(name: &str) -> Option<Key> {
+    let name = name.to_lowercase();
+
+    let key = match name.as_str() {
+        "space" => Key::Char(' '),
+        "tab" => Key::Char('\t'),
+        "cr" | "enter" | "return" => Key::Char('\n'),
+        "bs" | "backspace" => Key::Backspace,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "lt" => Key::Char('<'),
+        _ => {
+            let letter = name.strip_prefix("c-")?;
+            let mut chars = letter.chars();
+
+            match (chars.next(), chars.next()) {
+                (Some(letter @ 'a'..='z'), None) => Key::Char((letter as u8 - b'a' + 1) as char),
+                _ => return None,
+            }
+        }
+    };
+
+    Some(key)
+}
+
+/// Writes a key the way `parse_keys` reads it, for showing keys that have
+/// been typed.
+pub fn describe_key(key: &Key) -> String {
+    match key {
+        Key::Char(' ') => "<space>".to_string(),
+        Key::Char('\t') => "<tab>".to_string(),
+        Key::Char('\n') => "<cr>".to_string(),
+        Key::Char('<') => "<lt>".to_string(),
+        Key::Char(char @ '\u{1}'..='\u{1a}') => {
+            format!("<c-{}>", (*char as u8 - 1 + b'a') as char)
+        }
+        Key::Char(char) => char.to_string(),
+        Key::Escape => "<esc>".to_string(),
+        Key::Backspace => "<bs>".to_string(),
+        Key::Up => "<up>".to_string(),
+        Key::Down => "<down>".to_string(),
+        Key::Left => "<left>".to_string(),
+        Key::Right => "<right>".to_string(),
+        Key::Home => "<home>".to_string(),
+        Key::End => "<end>".to_string(),
+        _ => "<?>".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn keys(text: &str) -> Option<Vec<Key>> {
+        parse_keys(text, &Key::Char(','))
+    }
+
+    #[test]
+    fn key_parsing() {
+        assert_eq!(keys("j"), Some(vec![Key::Char('j')]));
+        assert_eq!(keys("gg"), Some(vec![Key::Char('g'), Key::Char('g')]));
+        assert_eq!(keys("<"), Some(vec![Key::Char('<')]));
+        assert_eq!(keys("<Space>"), Some(vec![Key::Char(' ')]));
+        assert_eq!(keys("<c-p>"), Some(vec![Key::Char('\u{10}')]));
+        assert_eq!(
+            keys("<leader>f<cr>"),
+            Some(vec![Key::Char(','), Key::Char('f'), Key::Char('\n')])
+        );
+        assert_eq!(
+            keys("<=>"),
+            Some(vec![Key::Char('<'), Key::Char('='), Key::Char('>')])
+        );
+        assert_eq!(keys("<f5>"), None);
+        assert_eq!(keys("<c-1>"), None);
+        assert_eq!(keys("<esc>"), None);
+        assert_eq!(keys(""), None);
+
+        for text in &["<space>", "<c-w>", "<lt>", "<up>", "<end>", "x"] {
+            assert_eq!(describe_key(&keys(text).unwrap()[0]), *text);
+        }
+    }
+
+    #[test]
+    fn sequence_lookup() {
+        let mut keymap = Keymap::default();
+        let normal = InputMode::Normal;
+        let lookup = |keymap: &Keymap, text: &str| keymap.lookup(normal, &keys(text).unwrap());
+
+        assert_eq!(lookup(&keymap, "g"), Lookup::Pending);
+        assert_eq!(lookup(&keymap, "gg"), Lookup::Bound(Binding::Top, None));
+        assert_eq!(lookup(&keymap, "gx"), Lookup::Unbound);
+        assert_eq!(lookup(&keymap, "m"), Lookup::Pending);
+        assert_eq!(
+            lookup(&keymap, "ma"),
+            Lookup::Bound(Binding::Bookmark, Some('a'))
+        );
+        assert_eq!(lookup(&keymap, "mab"), Lookup::Unbound);
+
+        // Binding a key replaces the longer sequences starting with it, and
+        // the other way around.
+        keymap.bind(normal, &keys("d").unwrap(), Binding::Trash);
+        assert_eq!(lookup(&keymap, "dd"), Lookup::Unbound);
+        keymap.bind(normal, &keys("qq").unwrap(), Binding::Quit);
+        assert_eq!(lookup(&keymap, "q"), Lookup::Pending);
+
+        keymap.unbind(normal, &keys("gg").unwrap());
+        keymap.unbind(normal, &keys("gh").unwrap());
+        assert_eq!(lookup(&keymap, "g"), Lookup::Unbound);
+    }
+
+    #[test]
+    fn binding_names() {
+        for &binding in Binding::ALL.iter() {
+            assert_eq!(Binding::from_name(binding.name()), Some(binding));
+        }
+    }
+}
@@ -1,39 +1,97 @@
 pub mod action;
 pub mod app_state;
+pub mod bookmarks;
+pub mod command;
+pub mod completion;
+pub mod config;
+pub mod date;
+pub mod error;
+pub mod file_ops;
+pub mod filter;
+pub mod finder;
+pub mod foreground;
+pub mod grep;
 pub mod input_state;
+pub mod journal;
+pub mod keymap;
+pub mod matcher;
+pub mod metadata;
+pub mod overlay;
+pub mod settings;
+pub mod shell;
+pub mod sort;
 pub mod terminal_context;
+#[cfg(unix)]
+pub mod terminal_input;
+pub mod trash;
 pub mod ui;
 pub mod virtual_screen;
 pub mod virtual_screen_buffer;
 
-use std::{env, panic, path::PathBuf, process};
+use std::{
+    env, panic,
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
 
 use clap::{App, Arg};
 
 use crate::{
-    action::Action, app_state::AppState, input_state::InputState,
+    action::Action, app_state::AppState, config::Config, input_state::InputState,
     terminal_context::TerminalContext, virtual_screen::VirtualScreen,
 };
 
+/// How often results from the finder and grep are picked up while they're
+/// still coming in.
+const BACKGROUND_WORK_INTERVAL: Duration = Duration::from_millis(50);
+
 struct AppConfig {
     print_working_directory: bool,
     start_dir: PathBuf,
+
+    /// What was read from the config file.
+    file: Config,
 }
 
-fn start(config: &AppConfig) {
-    let mut state = AppState::new(config.start_dir.clone());
-    let mut input_state = InputState::new();
+fn start(config: AppConfig) {
+    let Config {
+        settings,
+        theme,
+        keymap,
+    } = config.file;
+
+    let mut state = AppState::new(config.start_dir, settings);
+    let mut input_state = InputState::new(keymap);
     let mut context = TerminalContext::init();
     let (width, height) = context.get_terminal_size();
     let mut screen = VirtualScreen::new(width, height);
 
     loop {
+        state.poll_background_work();
         ui::adjust_entry_window(&mut state, &screen);
         screen.render_prepare(&context);
-        ui::render(&state, &input_state, &mut screen);
+        ui::render(&state, &input_state, &theme, &mut screen);
         screen.commit(&mut context);
 
-        if let Some(action) = input_state.process_input(&mut context) {
+        // Shell commands can take a while, so they're run once the screen
+        // shows that they've started.
+        if state.shell_command.is_some() {
+            state.run_shell_command();
+            input_state.sync_mode(&state);
+            continue;
+        }
+
+        // Nothing tells us when the finder or a grep has found more, so keys
+        // are only waited for a little while they're working, to draw their
+        // results as they come in.
+        let timeout = if state.has_background_work() {
+            Some(BACKGROUND_WORK_INTERVAL)
+        } else {
+            None
+        };
+
+        if let Some(action) = input_state.process_input(&mut context, &state, timeout) {
             match action {
                 Action::Quit => break,
                 Action::DebugDumpVisible => eprintln!("{}", screen.show_current_buffer()),
@@ -42,6 +100,21 @@ fn start(config: &AppConfig) {
             }
 
             state.process_action(action);
+
+            if state.should_quit {
+                break;
+            }
+
+            if let Some(command) = state.foreground_command.take() {
+                context.suspend();
+                let result = command.run(&mut context);
+                context.resume();
+                screen.refresh();
+
+                state.finish_foreground_command(&command, result);
+            }
+
+            input_state.sync_mode(&state);
         }
     }
 
@@ -67,6 +140,12 @@ fn main() {
                 .long("pwd")
                 .help("Prints the current directory to stderr when closing."),
         )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .help("The config file to use instead of msb/config.toml in the config directory."),
+        )
         .get_matches();
 
     let start_dir = match matches.value_of("START_DIR") {
@@ -76,12 +155,23 @@ fn main() {
 
     let print_working_directory = matches.is_present("pwd");
 
+    // Config errors are reported before the interface takes over the
+    // terminal, so that they can be read.
+    let config = match Config::load(matches.value_of("config").map(Path::new)) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
+
     let config = AppConfig {
         print_working_directory,
         start_dir,
+        file: config,
     };
 
-    let result = panic::catch_unwind(move || start(&config));
+    let result = panic::catch_unwind(move || start(config));
 
     if let Err(error) = result {
         let message = match error.downcast_ref::<&str>() {
@@ -0,0 +1,368 @@
+//! Matches entry names against what the user typed into the find and filter
+//! prompts, in one of several styles.
+
+use globset::{GlobBuilder, GlobMatcher};
+use regex::{Regex, RegexBuilder};
+
+/// How the text typed into the find prompt is matched against entry names.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MatchMode {
+    /// Names starting with the text, ignoring case.
+    #[default]
+    Prefix,
+
+    /// Names containing the text anywhere, ignoring case.
+    Substring,
+
+    /// Like `Substring`, but only ignoring case when the text is all lower
+    /// case.
+    SmartCase,
+
+    /// Names containing the characters of the text in order, with gaps
+    /// allowed, like fzf. Case is handled like `SmartCase`.
+    Fuzzy,
+
+    /// Shell-style globs like `*.rs`, ignoring case.
+    Glob,
+
+    /// Regular expressions, with case handled like `SmartCase`.
+    Regex,
+}
+
+impl MatchMode {
+    pub const ALL: [MatchMode; 6] = [
+        MatchMode::Prefix,
+        MatchMode::Substring,
+        MatchMode::SmartCase,
+        MatchMode::Fuzzy,
+        MatchMode::Glob,
+        MatchMode::Regex,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MatchMode::Prefix => "prefix",
+            MatchMode::Substring => "substring",
+            MatchMode::SmartCase => "smartcase",
+            MatchMode::Fuzzy => "fuzzy",
+            MatchMode::Glob => "glob",
+            MatchMode::Regex => "regex",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<MatchMode> {
+        MatchMode::ALL
+            .iter()
+            .cloned()
+            .find(|mode| mode.name() == name)
+    }
+
+    /// The mode after this one, for cycling through them from the prompt.
+    pub fn next(self) -> MatchMode {
+        let index = MatchMode::ALL
+            .iter()
+            .position(|&mode| mode == self)
+            .unwrap_or(0);
+
+        MatchMode::ALL[(index + 1) % MatchMode::ALL.len()]
+    }
+}
+
+/// Where a name matched and how well.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchResult {
+    /// Higher is better. Only fuzzy matches are scored; every other kind of
+    /// match scores zero.
+    pub score: i64,
+
+    /// The indices of the characters in the name that matched, for
+    /// highlighting. Globs don't say which characters matched, so this is
+    /// empty for them.
+    pub positions: Vec<usize>,
+}
+
+#[derive(Debug)]
+enum Pattern {
+    Text(Vec<char>),
+    Glob(GlobMatcher),
+    Regex(Regex),
+
+    /// A glob or regex that doesn't parse yet, usually because it's still
+    /// being typed. It never matches.
+    Invalid,
+}
+
+/// The text from a prompt, prepared for matching against many names.
+#[derive(Debug)]
+pub struct Matcher {
+    mode: MatchMode,
+    ignore_case: bool,
+    pattern: Pattern,
+}
+
+impl Matcher {
+    pub fn new(mode: MatchMode, target: &str) -> Matcher {
+        let ignore_case = match mode {
+            MatchMode::Prefix | MatchMode::Substring | MatchMode::Glob => true,
+            MatchMode::SmartCase | MatchMode::Fuzzy | MatchMode::Regex => {
+                !target.chars().any(char::is_uppercase)
+            }
+        };
+
+        let pattern = if target.is_empty() {
+            Pattern::Text(Vec::new())
+        } else {
+            match mode {
+                MatchMode::Glob => GlobBuilder::new(target)
+                    .case_insensitive(true)
+                    .build()
+                    .map(|glob| Pattern::Glob(glob.compile_matcher()))
+                    .unwrap_or(Pattern::Invalid),
+                MatchMode::Regex => RegexBuilder::new(target)
+                    .case_insensitive(ignore_case)
+                    .build()
+                    .map(Pattern::Regex)
+                    .unwrap_or(Pattern::Invalid),
+                _ => Pattern::Text(fold_case(target, ignore_case)),
+            }
+        };
+
+        Matcher {
+            mode,
+            ignore_case,
+            pattern,
+        }
+    }
+
+    pub fn mode(&self) -> MatchMode {
+        self.mode
+    }
+
+    /// Whether there's nothing to match against. An empty matcher matches
+    /// nothing.
+    pub fn is_empty(&self) -> bool {
+        match &self.pattern {
+            Pattern::Text(target) => target.is_empty(),
+            _ => false,
+        }
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        self.find(name).is_some()
+    }
+
+    pub fn find(&self, name: &str) -> Option<MatchResult> {
+        if self.is_empty() {
+            return None;
+        }
+
+        // Directories are shown with a trailing slash, which would get in the
+        // way of patterns like `*.app` or `app$`.
+        let bare_name = name.trim_end_matches('/');
+
+        match &self.pattern {
+            Pattern::Text(target) => {
+                let name = fold_case(name, self.ignore_case);
+
+                match self.mode {
+                    MatchMode::Prefix => {
+                        if name.starts_with(target) {
+                            Some(contiguous_match(0, target.len()))
+                        } else {
+                            None
+                        }
+                    }
+                    MatchMode::Fuzzy => fuzzy_match(&name, target),
+                    _ => name
+                        .windows(target.len())
+                        .position(|window| window == &target[..])
+                        .map(|start| contiguous_match(start, target.len())),
+                }
+            }
+            Pattern::Glob(glob) => {
+                if glob.is_match(bare_name) {
+                    Some(MatchResult {
+                        score: 0,
+                        positions: Vec::new(),
+                    })
+                } else {
+                    None
+                }
+            }
+            Pattern::Regex(regex) => regex.find(bare_name).map(|found| {
+                let start = bare_name[..found.start()].chars().count();
+                let length = found.as_str().chars().count();
+
+                contiguous_match(start, length)
+            }),
+            Pattern::Invalid => None,
+        }
+    }
+}
+
+/// Splits text into characters, lowering their case if asked. Characters are
+/// lowered one at a time so that indices line up with the original text.
+fn fold_case(text: &str, ignore_case: bool) -> Vec<char> {
+    text.chars()
+        .map(|char| {
+            if ignore_case {
+                char.to_lowercase().next().unwrap_or(char)
+            } else {
+                char
+            }
+        })
+        .collect()
+}
+
+fn contiguous_match(start: usize, length: usize) -> MatchResult {
+    MatchResult {
+        score: 0,
+        positions: (start..start + length).collect(),
+    }
+}
+
+const SCORE_MATCH: i64 = 16;
+const BONUS_BOUNDARY: i64 = 8;
+const BONUS_CONSECUTIVE: i64 = 8;
+const BONUS_FIRST_CHAR: i64 = 8;
+const PENALTY_GAP: i64 = 1;
+
+/// Finds the target's characters in order inside the name, like fzf's first
+/// algorithm: find the earliest place the match ends, then walk backwards to
+/// find the shortest match ending there.
+fn fuzzy_match(name: &[char], target: &[char]) -> Option<MatchResult> {
+    let mut target_index = 0;
+    let mut end = None;
+
+    for (index, &char) in name.iter().enumerate() {
+        if char == target[target_index] {
+            target_index += 1;
+
+            if target_index == target.len() {
+                end = Some(index);
+                break;
+            }
+        }
+    }
+
+    let end = end?;
+    let mut positions = Vec::with_capacity(target.len());
+    let mut target_index = target.len();
+
+    for index in (0..=end).rev() {
+        if name[index] == target[target_index - 1] {
+            positions.push(index);
+            target_index -= 1;
+
+            if target_index == 0 {
+                break;
+            }
+        }
+    }
+
+    positions.reverse();
+
+    Some(MatchResult {
+        score: fuzzy_score(name, &positions),
+        positions,
+    })
+}
+
+fn fuzzy_score(name: &[char], positions: &[usize]) -> i64 {
+    let mut score = 0;
+    let mut previous: Option<usize> = None;
+
+    for &position in positions {
+        score += SCORE_MATCH;
+
+        if position == 0 {
+            score += BONUS_FIRST_CHAR;
+        }
+
+        if is_boundary(name, position) {
+            score += BONUS_BOUNDARY;
+        }
+
+        match previous {
+            Some(previous) if previous + 1 == position => score += BONUS_CONSECUTIVE,
+            Some(previous) => score -= PENALTY_GAP * (position - previous - 1) as i64,
+            None => {}
+        }
+
+        previous = Some(position);
+    }
+
+    score
+}
+
+/// Whether a character starts a word, like the `b` in `foo_bar` or `fooBar`.
+fn is_boundary(name: &[char], position: usize) -> bool {
+    if position == 0 {
+        return true;
+    }
+
+    let before = name[position - 1];
+    let char = name[position];
+
+    match before {
+        '/' | '_' | '-' | '.' | ' ' => true,
+        _ => before.is_lowercase() && char.is_uppercase(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn positions(mode: MatchMode, target: &str, name: &str) -> Option<Vec<usize>> {
+        Matcher::new(mode, target)
+            .find(name)
+            .map(|result| result.positions)
+    }
+
+    #[test]
+    fn text_modes() {
+        assert_eq!(
+            positions(MatchMode::Prefix, "CA", "cargo"),
+            Some(vec![0, 1])
+        );
+        assert_eq!(positions(MatchMode::Prefix, "ar", "cargo"), None);
+        assert_eq!(
+            positions(MatchMode::Substring, "AR", "cargo"),
+            Some(vec![1, 2])
+        );
+        assert_eq!(
+            positions(MatchMode::SmartCase, "ar", "CARGO"),
+            Some(vec![1, 2])
+        );
+        assert_eq!(positions(MatchMode::SmartCase, "Ar", "cargo"), None);
+    }
+
+    #[test]
+    fn pattern_modes() {
+        assert!(Matcher::new(MatchMode::Glob, "*.RS").is_match("main.rs"));
+        assert!(!Matcher::new(MatchMode::Glob, "*.rs").is_match("main.rs.bak"));
+        assert!(Matcher::new(MatchMode::Glob, "src").is_match("src/"));
+        assert_eq!(
+            positions(MatchMode::Regex, "a.n", "main.rs"),
+            Some(vec![1, 2, 3])
+        );
+        assert_eq!(positions(MatchMode::Regex, "(", "main.rs"), None);
+    }
+
+    #[test]
+    fn fuzzy_matching() {
+        assert_eq!(
+            positions(MatchMode::Fuzzy, "mrs", "main.rs"),
+            Some(vec![0, 5, 6])
+        );
+        assert_eq!(positions(MatchMode::Fuzzy, "xyz", "main.rs"), None);
+
+        // The shortest match ending at the earliest place is used.
+        assert_eq!(positions(MatchMode::Fuzzy, "ab", "a_xab"), Some(vec![3, 4]));
+
+        let matcher = Matcher::new(MatchMode::Fuzzy, "as");
+        let score = |name| matcher.find(name).unwrap().score;
+        assert!(score("app_state.rs") > score("cargo_fmt.rs"));
+    }
+}
@@ -0,0 +1,211 @@
+//! Extra information about entries that can be shown in columns next to their
+//! names. It's read once when the directory is read, not every frame.
+
+use std::{collections::HashMap, fs, path::Path, time::SystemTime};
+
+use crate::date::DateTime;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EntryMetadata {
+    /// The size in bytes, only known for files.
+    pub size: Option<u64>,
+    pub modified: Option<SystemTime>,
+
+    /// The Unix mode bits, including the file type.
+    pub mode: Option<u32>,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+}
+
+impl EntryMetadata {
+    /// Reads what's known about an entry. Anything that can't be read is left
+    /// out instead of failing, since it's only for show. Owners are left out
+    /// too unless there are names to look them up in.
+    pub fn read(path: &Path, names: Option<&OwnerNames>) -> EntryMetadata {
+        let mut metadata = EntryMetadata::default();
+
+        // Symlinks show the size and time of what they point to, but the mode
+        // of the link itself so they can be told apart.
+        if let Ok(target) = fs::metadata(path) {
+            if target.is_file() {
+                metadata.size = Some(target.len());
+            }
+
+            metadata.modified = target.modified().ok();
+        }
+
+        if let Ok(link) = fs::symlink_metadata(path) {
+            read_unix_fields(&link, names, &mut metadata);
+        }
+
+        metadata
+    }
+
+    pub fn size_text(&self) -> String {
+        match self.size {
+            Some(size) => format_size(size),
+            None => "-".to_string(),
+        }
+    }
+
+    pub fn modified_text(&self) -> String {
+        match self.modified {
+            Some(time) => DateTime::from_system_time(time).to_short_string(),
+            None => "-".to_string(),
+        }
+    }
+
+    pub fn permissions_text(&self) -> String {
+        match self.mode {
+            Some(mode) => format_mode(mode),
+            None => "-".to_string(),
+        }
+    }
+
+    pub fn owner_text(&self) -> String {
+        let name_or_dash = |name: &Option<String>| match name {
+            Some(name) => name.clone(),
+            None => "-".to_string(),
+        };
+
+        format!(
+            "{}:{}",
+            name_or_dash(&self.owner),
+            name_or_dash(&self.group)
+        )
+    }
+}
+
+#[cfg(unix)]
+fn read_unix_fields(link: &fs::Metadata, names: Option<&OwnerNames>, metadata: &mut EntryMetadata) {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.mode = Some(link.mode());
+
+    if let Some(names) = names {
+        metadata.owner = Some(names.user(link.uid()));
+        metadata.group = Some(names.group(link.gid()));
+    }
+}
+
+#[cfg(not(unix))]
+fn read_unix_fields(
+    _link: &fs::Metadata,
+    _names: Option<&OwnerNames>,
+    _metadata: &mut EntryMetadata,
+) {
+}
+
+/// Names of users and groups, looked up from `/etc/passwd` and `/etc/group`.
+/// Users that only exist in a directory service like LDAP show up as numbers.
+#[derive(Debug, Default)]
+pub struct OwnerNames {
+    users: HashMap<u32, String>,
+    groups: HashMap<u32, String>,
+}
+
+impl OwnerNames {
+    pub fn load() -> OwnerNames {
+        OwnerNames {
+            users: read_id_file(Path::new("/etc/passwd")),
+            groups: read_id_file(Path::new("/etc/group")),
+        }
+    }
+
+    pub fn user(&self, id: u32) -> String {
+        self.users
+            .get(&id)
+            .cloned()
+            .unwrap_or_else(|| id.to_string())
+    }
+
+    pub fn group(&self, id: u32) -> String {
+        self.groups
+            .get(&id)
+            .cloned()
+            .unwrap_or_else(|| id.to_string())
+    }
+}
+
+/// Reads a file shaped like `/etc/passwd`, where each line is a name and an ID
+/// separated by other colon-separated fields.
+fn read_id_file(path: &Path) -> HashMap<u32, String> {
+    let contents = fs::read_to_string(path).unwrap_or_default();
+    let mut names = HashMap::new();
+
+    for line in contents.lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+
+        if let (Some(name), Some(id)) = (fields.first(), fields.get(2)) {
+            if let Ok(id) = id.parse() {
+                names.entry(id).or_insert_with(|| name.to_string());
+            }
+        }
+    }
+
+    names
+}
+
+/// Formats a size in bytes like `512`, `1.5K`, or `12M`.
+pub fn format_size(size: u64) -> String {
+    const UNITS: [char; 5] = ['K', 'M', 'G', 'T', 'P'];
+
+    if size < 1024 {
+        return size.to_string();
+    }
+
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if value < 10.0 {
+        format!("{:.1}{}", value, UNITS[unit])
+    } else {
+        format!("{:.0}{}", value, UNITS[unit])
+    }
+}
+
+/// Formats Unix mode bits like `ls -l` does, like `drwxr-xr-x`.
+pub fn format_mode(mode: u32) -> String {
+    let kind = match mode & 0o170_000 {
+        0o040_000 => 'd',
+        0o120_000 => 'l',
+        _ => '-',
+    };
+
+    let mut text = String::with_capacity(10);
+    text.push(kind);
+
+    for shift in &[6, 3, 0] {
+        let bits = (mode >> shift) & 0o7;
+        text.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        text.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        text.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn size_formatting() {
+        assert_eq!(format_size(0), "0");
+        assert_eq!(format_size(1023), "1023");
+        assert_eq!(format_size(1536), "1.5K");
+        assert_eq!(format_size(20 * 1024 * 1024), "20M");
+    }
+
+    #[test]
+    fn mode_formatting() {
+        assert_eq!(format_mode(0o040_755), "drwxr-xr-x");
+        assert_eq!(format_mode(0o100_644), "-rw-r--r--");
+        assert_eq!(format_mode(0o120_777), "lrwxrwxrwx");
+    }
+}
@@ -0,0 +1,154 @@
+use std::path::PathBuf;
+
+use crate::{grep::GrepMatch, trash::TrashItem};
+
+/// What's being listed in an overlay.
+#[derive(Debug)]
+pub enum OverlayContent {
+    Trash(Vec<TrashItem>),
+    Bookmarks(Vec<(char, PathBuf)>),
+
+    /// Files found by the fuzzy finder, relative to where it started, best
+    /// match first.
+    Finder {
+        results: Vec<PathBuf>,
+        searched: usize,
+        done: bool,
+    },
+
+    /// Lines found by `:grep`.
+    Grep {
+        pattern: String,
+        matches: Vec<GrepMatch>,
+        done: bool,
+    },
+
+    /// What a command run with `:!` printed, and the status it exited with,
+    /// if it wasn't killed by a signal.
+    Output {
+        command: String,
+        lines: Vec<String>,
+        code: Option<i32>,
+    },
+}
+
+/// Describes how a command exited, like `exited with status 1`.
+pub fn describe_exit(code: Option<i32>) -> String {
+    match code {
+        Some(code) => format!("exited with status {}", code),
+        None => "was killed by a signal".to_string(),
+    }
+}
+
+/// A list drawn on top of the directory listing, like the trash browser or
+/// the list of bookmarks. While an overlay is open, movement actions move its
+/// cursor instead of the one in the directory listing.
+#[derive(Debug)]
+pub struct Overlay {
+    pub content: OverlayContent,
+    pub cursor: usize,
+    pub window_start: usize,
+}
+
+impl Overlay {
+    pub fn new(content: OverlayContent) -> Overlay {
+        Overlay {
+            content,
+            cursor: 0,
+            window_start: 0,
+        }
+    }
+
+    pub fn title(&self) -> String {
+        match &self.content {
+            OverlayContent::Trash(items) => format!("Trash ({} items)", items.len()),
+            OverlayContent::Bookmarks(_) => "Bookmarks".to_string(),
+            OverlayContent::Finder {
+                results,
+                searched,
+                done,
+            } => format!(
+                "Files ({} of {}{})",
+                results.len(),
+                searched,
+                if *done { "" } else { ", searching..." }
+            ),
+            OverlayContent::Grep {
+                pattern,
+                matches,
+                done,
+            } => format!(
+                "grep {} ({} matches{})",
+                pattern,
+                matches.len(),
+                if *done { "" } else { ", searching..." }
+            ),
+            OverlayContent::Output { command, .. } => format!("Output of {}", command),
+        }
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        match &self.content {
+            OverlayContent::Trash(items) => items
+                .iter()
+                .map(|item| format!("{}  {}", item.deletion_date, item.original_path.display()))
+                .collect(),
+            OverlayContent::Bookmarks(marks) => marks
+                .iter()
+                .map(|(letter, path)| format!("{}  {}", letter, path.display()))
+                .collect(),
+            OverlayContent::Finder { results, .. } => results
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect(),
+            OverlayContent::Grep { matches, .. } => matches
+                .iter()
+                .map(|found| {
+                    format!(
+                        "{}:{}: {}",
+                        found.path.display(),
+                        found.line_number,
+                        found.text
+                    )
+                })
+                .collect(),
+            OverlayContent::Output { lines, .. } => lines.clone(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.content {
+            OverlayContent::Trash(items) => items.len(),
+            OverlayContent::Bookmarks(marks) => marks.len(),
+            OverlayContent::Finder { results, .. } => results.len(),
+            OverlayContent::Grep { matches, .. } => matches.len(),
+            OverlayContent::Output { lines, .. } => lines.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn up(&mut self, count: u64) {
+        self.cursor = self.cursor.saturating_sub(count as usize);
+    }
+
+    pub fn down(&mut self, count: u64) {
+        let last = self.len().saturating_sub(1);
+        self.cursor = (self.cursor + count as usize).min(last);
+    }
+
+    pub fn top(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn bottom(&mut self) {
+        self.cursor = self.len().saturating_sub(1);
+    }
+
+    /// Keeps the cursor in bounds after the content changes.
+    pub fn clamp_cursor(&mut self) {
+        self.cursor = self.cursor.min(self.len().saturating_sub(1));
+    }
+}
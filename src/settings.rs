@@ -0,0 +1,154 @@
+//! Options that change how the directory listing is shown, changed at runtime
+//! with `:set` and `:sort`.
+
+use globset::Glob;
+
+use crate::{error::AppError, matcher::MatchMode, sort::Sort};
+
+/// Which metadata columns are shown to the right of entry names.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Columns {
+    pub size: bool,
+    pub modified: bool,
+    pub permissions: bool,
+    pub owner: bool,
+}
+
+impl Columns {
+    pub fn any(&self) -> bool {
+        self.size || self.modified || self.permissions || self.owner
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    pub columns: Columns,
+    pub sort: Sort,
+
+    /// Whether entries starting with a `.` are listed.
+    pub show_hidden: bool,
+
+    /// Whether entries matched by `.gitignore` and `.ignore` files are left
+    /// out of the listing.
+    pub use_ignore_files: bool,
+
+    /// Globs like `*.pyc` for entries to leave out of the listing.
+    pub ignore_patterns: Vec<String>,
+
+    /// How the find and filter prompts match entry names.
+    pub find_mode: MatchMode,
+}
+
+impl Settings {
+    /// Every option that `set` knows about, for completing them. Options that
+    /// take a value end with `=`.
+    pub const OPTION_NAMES: [&'static str; 10] = [
+        "size",
+        "mtime",
+        "perms",
+        "owner",
+        "reverse",
+        "dirsfirst",
+        "hidden",
+        "gitignore",
+        "ignore=",
+        "find=",
+    ];
+
+    /// Changes an option the way Vim's `:set` does: `name` turns an option
+    /// on, `noname` turns it off, `name!` toggles it, and `name=value` gives
+    /// it a value.
+    pub fn set(&mut self, option: &str) -> Result<(), AppError> {
+        let unknown = || AppError::UnknownOption(option.to_string());
+
+        let mut parts = option.splitn(2, '=');
+
+        if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+            return self.set_value(name, value).ok_or_else(unknown)?;
+        }
+
+        let toggled = option.trim_end_matches('!');
+
+        if toggled.len() < option.len() {
+            let flag = self.flag(toggled).ok_or_else(unknown)?;
+            *flag = !*flag;
+        } else if let Some(flag) = self.flag(option) {
+            *flag = true;
+        } else if let Some(negated) = option.strip_prefix("no") {
+            *self.flag(negated).ok_or_else(unknown)? = false;
+        } else {
+            return Err(unknown());
+        }
+
+        Ok(())
+    }
+
+    fn set_value(&mut self, name: &str, value: &str) -> Option<Result<(), AppError>> {
+        match name {
+            "ignore" => {
+                let patterns: Vec<String> = value
+                    .split(',')
+                    .filter(|pattern| !pattern.is_empty())
+                    .map(str::to_string)
+                    .collect();
+
+                for pattern in &patterns {
+                    if let Err(source) = Glob::new(pattern) {
+                        return Some(Err(AppError::InvalidPattern {
+                            pattern: pattern.clone(),
+                            source,
+                        }));
+                    }
+                }
+
+                self.ignore_patterns = patterns;
+                Some(Ok(()))
+            }
+            "find" => {
+                let mode = MatchMode::from_name(value)?;
+                self.find_mode = mode;
+                Some(Ok(()))
+            }
+            _ => None,
+        }
+    }
+
+    fn flag(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "size" => Some(&mut self.columns.size),
+            "mtime" => Some(&mut self.columns.modified),
+            "perms" => Some(&mut self.columns.permissions),
+            "owner" => Some(&mut self.columns.owner),
+            "reverse" => Some(&mut self.sort.reverse),
+            "dirsfirst" => Some(&mut self.sort.directories_first),
+            "hidden" => Some(&mut self.show_hidden),
+            "gitignore" => Some(&mut self.use_ignore_files),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn set_flags() {
+        let mut settings = Settings::default();
+
+        settings.set("hidden").unwrap();
+        assert!(settings.show_hidden);
+
+        settings.set("nohidden").unwrap();
+        assert!(!settings.show_hidden);
+
+        settings.set("hidden!").unwrap();
+        assert!(settings.show_hidden);
+
+        assert!(settings.set("n").is_err());
+        assert!(settings.set("no").is_err());
+        assert!(settings.set("日本").is_err());
+        assert!(settings.set("no日本").is_err());
+        assert!(settings.set("日本!").is_err());
+    }
+}
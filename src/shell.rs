@@ -0,0 +1,188 @@
+//! Runs commands typed after `:!` through the system shell, filling in
+//! placeholders for the entries being browsed.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+/// The entries that placeholders in a shell command stand for.
+pub struct Placeholders<'a> {
+    /// What `%` becomes: the entry under the cursor.
+    pub current: Option<&'a Path>,
+
+    /// What `%s` becomes: the selected entries.
+    pub selection: &'a [PathBuf],
+
+    /// What `%d` becomes: the working directory.
+    pub directory: &'a Path,
+}
+
+/// A command from `:!` that's waiting to be run.
+#[derive(Debug, Clone)]
+pub struct PendingCommand {
+    /// The command as it was typed, for showing to the user.
+    pub command: String,
+
+    /// The command with its placeholders filled in, to hand to the shell.
+    pub expanded: String,
+}
+
+/// Fills in the placeholders in a command, quoting each path so the shell
+/// sees it as one word. `%%` stands for a plain `%`.
+pub fn expand(command: &str, placeholders: &Placeholders) -> String {
+    let mut expanded = String::new();
+    let mut chars = command.chars().peekable();
+
+    while let Some(char) = chars.next() {
+        if char != '%' {
+            expanded.push(char);
+            continue;
+        }
+
+        match chars.peek() {
+            Some('%') => {
+                chars.next();
+                expanded.push('%');
+            }
+            Some('s') => {
+                chars.next();
+
+                let quoted: Vec<String> = placeholders
+                    .selection
+                    .iter()
+                    .map(|path| quote(path))
+                    .collect();
+                expanded.push_str(&quoted.join(" "));
+            }
+            Some('d') => {
+                chars.next();
+                expanded.push_str(&quote(placeholders.directory));
+            }
+            _ => {
+                if let Some(current) = placeholders.current {
+                    expanded.push_str(&quote(current));
+                }
+            }
+        }
+    }
+
+    expanded
+}
+
+/// Quotes a path for the shell. Paths made only of characters that are safe
+/// everywhere are left alone to keep commands readable.
+#[cfg(not(windows))]
+pub fn quote(path: &Path) -> String {
+    let text = path.to_string_lossy();
+    let is_safe = !text.is_empty()
+        && text
+            .chars()
+            .all(|char| char.is_ascii_alphanumeric() || "/._-+,:@".contains(char));
+
+    if is_safe {
+        text.to_string()
+    } else {
+        format!("'{}'", text.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(windows)]
+pub fn quote(path: &Path) -> String {
+    quote_for_cmd(&path.to_string_lossy())
+}
+
+/// Quotes text for `cmd`, which expands variables like `%PATH%` even inside
+/// quotes. Each `%` is put outside the quotes and escaped with `^` instead.
+/// Names on Windows can't contain `"`, so there's no need to escape it.
+#[cfg(any(windows, test))]
+fn quote_for_cmd(text: &str) -> String {
+    format!("\"{}\"", text.replace('%', "\"^%\""))
+}
+
+/// The shell that command lines are run through.
+#[cfg(not(windows))]
+pub const SHELL: &str = "sh";
+
+#[cfg(windows)]
+pub const SHELL: &str = "cmd";
+
+/// Builds a process that runs a command line through the shell.
+#[cfg(not(windows))]
+pub fn shell_command(command: &str) -> Command {
+    let mut process = Command::new(SHELL);
+    process.arg("-c").arg(command);
+    process
+}
+
+#[cfg(windows)]
+pub fn shell_command(command: &str) -> Command {
+    use std::os::windows::process::CommandExt;
+
+    // cmd reads its command line itself instead of splitting it into
+    // arguments, so the command is passed along exactly as it was written.
+    // Quoting it as an argument would escape the quotes that `quote` added.
+    let mut process = Command::new(SHELL);
+    process.arg("/C").raw_arg(command);
+    process
+}
+
+/// Runs a command through the shell, collecting everything it prints. The
+/// command gets no input, so it can't get stuck waiting for some.
+pub fn run_captured(command: &str, directory: &Path) -> io::Result<Output> {
+    shell_command(command)
+        .current_dir(directory)
+        .stdin(Stdio::null())
+        .output()
+}
+
+/// Splits what a command printed into lines that are safe to draw, with tabs
+/// expanded and other control characters taken out.
+pub fn output_lines(output: &Output) -> Vec<String> {
+    let mut text = String::from_utf8_lossy(&output.stdout).to_string();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+
+    text.lines()
+        .map(|line| {
+            line.replace('\t', "    ")
+                .chars()
+                .filter(|char| !char.is_control())
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(all(test, not(windows)))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn placeholder_expansion() {
+        let selection = [PathBuf::from("/tmp/a b"), PathBuf::from("/tmp/it's")];
+        let placeholders = Placeholders {
+            current: Some(Path::new("/tmp/a b")),
+            selection: &selection,
+            directory: Path::new("/tmp"),
+        };
+
+        assert_eq!(expand("wc -l %", &placeholders), "wc -l '/tmp/a b'");
+        assert_eq!(
+            expand("tar cf out.tar %s", &placeholders),
+            "tar cf out.tar '/tmp/a b' '/tmp/it'\\''s'"
+        );
+        assert_eq!(
+            expand("ls %d; echo 100%%", &placeholders),
+            "ls /tmp; echo 100%"
+        );
+    }
+
+    #[test]
+    fn cmd_quoting() {
+        assert_eq!(quote_for_cmd("C:\\a b"), "\"C:\\a b\"");
+        assert_eq!(
+            quote_for_cmd("C:\\%PATH%.txt"),
+            "\"C:\\\"^%\"PATH\"^%\".txt\""
+        );
+    }
+}
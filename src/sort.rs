@@ -0,0 +1,230 @@
+//! The orders that entries in the listing can be sorted in.
+
+use std::{cmp::Ordering, path::Path};
+
+use crate::app_state::{FileEntry, FileEntryKind};
+
+/// What entries are compared by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    /// Case-insensitive name, like `file10` before `file2`.
+    Name,
+
+    /// Name, but with runs of digits compared as numbers, like `file2` before
+    /// `file10`.
+    Natural,
+
+    /// Largest first, like `ls -S`.
+    Size,
+
+    /// Most recently modified first, like `ls -t`.
+    Modified,
+
+    /// Extension, then name.
+    Extension,
+
+    /// Directories, then symlinks, then files, then anything else.
+    Kind,
+}
+
+impl SortKey {
+    pub const ALL: [SortKey; 6] = [
+        SortKey::Name,
+        SortKey::Natural,
+        SortKey::Size,
+        SortKey::Modified,
+        SortKey::Extension,
+        SortKey::Kind,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Natural => "natural",
+            SortKey::Size => "size",
+            SortKey::Modified => "mtime",
+            SortKey::Extension => "extension",
+            SortKey::Kind => "kind",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SortKey> {
+        SortKey::ALL.iter().cloned().find(|key| key.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sort {
+    pub key: SortKey,
+    pub reverse: bool,
+
+    /// Keeps directories above files no matter what they're sorted by.
+    pub directories_first: bool,
+}
+
+impl Default for Sort {
+    fn default() -> Sort {
+        Sort {
+            key: SortKey::Name,
+            reverse: false,
+            directories_first: true,
+        }
+    }
+}
+
+impl Sort {
+    /// Describes the sort for the header bar, like `size, reversed`.
+    pub fn describe(&self) -> String {
+        let mut text = self.key.name().to_string();
+
+        if self.reverse {
+            text.push_str(", reversed");
+        }
+
+        if self.directories_first {
+            text.push_str(", dirs first");
+        }
+
+        text
+    }
+
+    /// Compares two entries. The parent directory always comes first, even
+    /// when the order is reversed.
+    pub fn compare(&self, a: &FileEntry, b: &FileEntry) -> Ordering {
+        match (a.kind, b.kind) {
+            (FileEntryKind::Parent, _) => return Ordering::Less,
+            (_, FileEntryKind::Parent) => return Ordering::Greater,
+            _ => {}
+        }
+
+        if self.directories_first {
+            match (a.kind, b.kind) {
+                (FileEntryKind::Directory, FileEntryKind::File) => return Ordering::Less,
+                (FileEntryKind::File, FileEntryKind::Directory) => return Ordering::Greater,
+                _ => {}
+            }
+        }
+
+        let ordering = self.compare_by_key(a, b);
+
+        if self.reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+
+    fn compare_by_key(&self, a: &FileEntry, b: &FileEntry) -> Ordering {
+        let by_name = || a.display.to_lowercase().cmp(&b.display.to_lowercase());
+
+        match self.key {
+            SortKey::Name => by_name(),
+            SortKey::Natural => natural_cmp(&a.display.to_lowercase(), &b.display.to_lowercase()),
+            SortKey::Size => {
+                let a_size = a.metadata.size.unwrap_or(0);
+                let b_size = b.metadata.size.unwrap_or(0);
+
+                b_size.cmp(&a_size).then_with(by_name)
+            }
+            SortKey::Modified => b
+                .metadata
+                .modified
+                .cmp(&a.metadata.modified)
+                .then_with(by_name),
+            SortKey::Extension => extension(&a.path)
+                .cmp(&extension(&b.path))
+                .then_with(by_name),
+            SortKey::Kind => kind_rank(a).cmp(&kind_rank(b)).then_with(by_name),
+        }
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+fn kind_rank(entry: &FileEntry) -> u8 {
+    match entry.metadata.mode.map(|mode| mode & 0o170_000) {
+        Some(0o040_000) => 0,
+        Some(0o120_000) => 1,
+        Some(0o100_000) => 2,
+        Some(_) => 3,
+        None => match entry.kind {
+            FileEntryKind::File => 2,
+            _ => 0,
+        },
+    }
+}
+
+/// Compares strings so that runs of digits are compared by their value, like
+/// `file2` before `file10`. When the values are equal, fewer leading zeros
+/// comes first.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+
+    loop {
+        let (a_char, b_char) = match (a_chars.peek(), b_chars.peek()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(&a_char), Some(&b_char)) => (a_char, b_char),
+        };
+
+        if a_char.is_ascii_digit() && b_char.is_ascii_digit() {
+            let a_digits = take_digits(&mut a_chars);
+            let b_digits = take_digits(&mut b_chars);
+            let a_value = a_digits.trim_start_matches('0');
+            let b_value = b_digits.trim_start_matches('0');
+
+            let ordering = a_value
+                .len()
+                .cmp(&b_value.len())
+                .then_with(|| a_value.cmp(b_value))
+                .then_with(|| a_digits.len().cmp(&b_digits.len()));
+
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        } else {
+            if a_char != b_char {
+                return a_char.cmp(&b_char);
+            }
+
+            a_chars.next();
+            b_chars.next();
+        }
+    }
+}
+
+fn take_digits<I: Iterator<Item = char>>(chars: &mut std::iter::Peekable<I>) -> String {
+    let mut digits = String::new();
+
+    while let Some(&char) = chars.peek() {
+        if !char.is_ascii_digit() {
+            break;
+        }
+
+        digits.push(char);
+        chars.next();
+    }
+
+    digits
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn natural_ordering() {
+        assert_eq!(natural_cmp("file2", "file10"), Ordering::Less);
+        assert_eq!(natural_cmp("file10", "file2"), Ordering::Greater);
+        assert_eq!(natural_cmp("file02", "file2"), Ordering::Greater);
+        assert_eq!(natural_cmp("a1b2", "a1b2"), Ordering::Equal);
+        assert_eq!(natural_cmp("v1.9", "v1.10"), Ordering::Less);
+        assert_eq!(natural_cmp("abc", "abcd"), Ordering::Less);
+    }
+}
@@ -1,43 +1,137 @@
 use std::{
     io,
+    process::Stdio,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use all_term::{terminal, Style, Terminal};
 
+#[cfg(unix)]
+use crate::terminal_input::TerminalInput;
+
 pub use all_term::{Color, Key};
 
 pub struct TerminalContext {
     terminal: Arc<Mutex<Terminal>>,
+
+    /// Where keys come from. all_term keeps reading keys even while another
+    /// program is using the terminal, so it's only used for that where
+    /// there's nothing better yet.
+    #[cfg(unix)]
+    input: TerminalInput,
 }
 
 impl TerminalContext {
+    #[cfg(unix)]
+    pub fn init() -> TerminalContext {
+        // all_term starts reading stdin as soon as it's created, so the
+        // terminal has to be moved off of stdin before that.
+        let input = TerminalInput::from_stdin().expect("Could not read from the terminal");
+        let mut context = TerminalContext {
+            terminal: terminal(),
+            input,
+        };
+
+        context.resume();
+        context
+    }
+
+    #[cfg(not(unix))]
     pub fn init() -> TerminalContext {
-        let terminal = terminal();
+        let mut context = TerminalContext {
+            terminal: terminal(),
+        };
+
+        context.resume();
+        context
+    }
 
+    /// Hands the terminal back to its normal state so that another program
+    /// can use it, until `resume` is called.
+    pub fn suspend(&mut self) {
         {
-            let mut handle = terminal.lock().unwrap();
-            handle.enable_raw_mode();
-            handle.enable_alternate_screen();
+            let mut handle = self.terminal.lock().unwrap();
+            handle.show_cursor();
+            handle.disable_alternate_screen();
         }
 
-        TerminalContext { terminal }
+        self.disable_raw_mode();
     }
 
+    pub fn resume(&mut self) {
+        self.enable_raw_mode();
+
+        let mut handle = self.terminal.lock().unwrap();
+        handle.enable_alternate_screen();
+    }
+
+    #[cfg(unix)]
+    fn enable_raw_mode(&mut self) {
+        self.input
+            .enable_raw_mode()
+            .expect("Could not enable raw mode");
+    }
+
+    #[cfg(not(unix))]
+    fn enable_raw_mode(&mut self) {
+        let mut handle = self.terminal.lock().unwrap();
+        handle.enable_raw_mode();
+    }
+
+    #[cfg(unix)]
+    fn disable_raw_mode(&mut self) {
+        self.input
+            .disable_raw_mode()
+            .expect("Could not disable raw mode");
+    }
+
+    #[cfg(not(unix))]
+    fn disable_raw_mode(&mut self) {
+        let mut handle = self.terminal.lock().unwrap();
+        handle.disable_raw_mode();
+    }
+
+    #[cfg(unix)]
+    pub fn get_terminal_size(&self) -> (usize, usize) {
+        self.input.size().expect("Could not get terminal size")
+    }
+
+    #[cfg(not(unix))]
     pub fn get_terminal_size(&self) -> (usize, usize) {
         let handle = self.terminal.lock().unwrap();
         handle.get_size()
     }
 
-    pub fn read_key(&mut self) -> Key {
+    /// Waits for a key to be pressed, giving up once `timeout` has passed.
+    #[cfg(unix)]
+    pub fn read_key(&mut self, timeout: Option<Duration>) -> Option<Key> {
+        self.input.read_key(timeout)
+    }
+
+    /// all_term can only wait for a key for as long as it takes, so there's
+    /// no timeout here.
+    #[cfg(not(unix))]
+    pub fn read_key(&mut self, _timeout: Option<Duration>) -> Option<Key> {
         let mut handle = self.terminal.lock().unwrap();
-        handle.read_key()
+        Some(handle.read_key())
+    }
+
+    /// What a program that takes over the terminal should read keys from.
+    #[cfg(unix)]
+    pub fn stdin_for_child(&self) -> io::Result<Stdio> {
+        self.input.stdin_for_child()
+    }
+
+    #[cfg(not(unix))]
+    pub fn stdin_for_child(&self) -> io::Result<Stdio> {
+        Ok(Stdio::inherit())
     }
 
     pub fn read_char(&mut self) -> io::Result<char> {
         // TODO: Port everything from read_char to read_key
-        match self.read_key() {
-            Key::Char(char) => Ok(char),
+        match self.read_key(None) {
+            Some(Key::Char(char)) => Ok(char),
             _ => Ok(' '),
         }
     }
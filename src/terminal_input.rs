@@ -0,0 +1,353 @@
+//! Reads keys straight from the terminal on Unix-like platforms.
+//!
+//! all_term reads keys on a thread that never stops reading stdin, even while
+//! an editor or a shell that we started is using the terminal, so some of the
+//! keys typed into those ended up with us instead. It also can't stop waiting
+//! for a key when there's something else to do. So before all_term starts, the
+//! terminal is moved off of stdin, which is left as an empty pipe for its
+//! thread to wait on forever, and keys are only read here while we're actually
+//! waiting for one.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, Read},
+    mem,
+    os::unix::io::{AsRawFd, FromRawFd},
+    process::Stdio,
+    time::Duration,
+};
+
+use all_term::Key;
+
+const ESC: u8 = 27;
+
+/// How long to wait for the rest of an escape sequence or of a character that
+/// arrived in pieces. Terminals send those all at once, so anything slower was
+/// typed by hand, like Escape and then `[`.
+const SEQUENCE_TIMEOUT: Duration = Duration::from_millis(20);
+
+pub struct TerminalInput {
+    tty: File,
+
+    /// How the terminal was set up before raw mode was turned on.
+    original_mode: Option<libc::termios>,
+
+    /// Bytes that were read but haven't been turned into keys yet.
+    buffer: VecDeque<u8>,
+}
+
+impl TerminalInput {
+    /// Takes the terminal that stdin is connected to, leaving an empty pipe
+    /// in its place. This has to happen before all_term starts reading stdin.
+    pub fn from_stdin() -> io::Result<TerminalInput> {
+        unsafe {
+            let tty = check(libc::fcntl(libc::STDIN_FILENO, libc::F_DUPFD_CLOEXEC, 0))?;
+            let tty = File::from_raw_fd(tty);
+
+            let mut pipe = [0; 2];
+            check(libc::pipe(pipe.as_mut_ptr()))?;
+
+            // all_term's thread panics if stdin ends, so the other end of the
+            // pipe is left open for good. Programs we start don't need it.
+            check(libc::fcntl(pipe[1], libc::F_SETFD, libc::FD_CLOEXEC))?;
+            check(libc::dup2(pipe[0], libc::STDIN_FILENO))?;
+            libc::close(pipe[0]);
+
+            Ok(TerminalInput::new(tty))
+        }
+    }
+
+    fn new(tty: File) -> TerminalInput {
+        TerminalInput {
+            tty,
+            original_mode: None,
+            buffer: VecDeque::new(),
+        }
+    }
+
+    /// Turns off echoing and line buffering, so that keys arrive as soon as
+    /// they're pressed.
+    pub fn enable_raw_mode(&mut self) -> io::Result<()> {
+        if self.original_mode.is_some() {
+            return Ok(());
+        }
+
+        let fd = self.tty.as_raw_fd();
+        let mut mode: libc::termios = unsafe { mem::zeroed() };
+        check(unsafe { libc::tcgetattr(fd, &mut mode) })?;
+
+        let mut raw = mode;
+        raw.c_lflag &= !(libc::ECHO | libc::ICANON);
+        check(unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) })?;
+
+        self.original_mode = Some(mode);
+        Ok(())
+    }
+
+    pub fn disable_raw_mode(&mut self) -> io::Result<()> {
+        if let Some(mode) = self.original_mode.take() {
+            check(unsafe { libc::tcsetattr(self.tty.as_raw_fd(), libc::TCSANOW, &mode) })?;
+        }
+
+        Ok(())
+    }
+
+    /// The width and height of the terminal.
+    pub fn size(&self) -> io::Result<(usize, usize)> {
+        let mut size: libc::winsize = unsafe { mem::zeroed() };
+        check(unsafe { libc::ioctl(self.tty.as_raw_fd(), libc::TIOCGWINSZ, &mut size) })?;
+
+        Ok((usize::from(size.ws_col), usize::from(size.ws_row)))
+    }
+
+    /// The terminal, for a program that takes it over to read keys from.
+    pub fn stdin_for_child(&self) -> io::Result<Stdio> {
+        Ok(Stdio::from(self.tty.try_clone()?))
+    }
+
+    /// Waits for a key, giving up once `timeout` has passed. `None` means that
+    /// no key came, either because time ran out or because the wait was
+    /// interrupted, like by the terminal being resized.
+    pub fn read_key(&mut self, timeout: Option<Duration>) -> Option<Key> {
+        loop {
+            if self.buffer.is_empty() && !self.fill(timeout) {
+                return None;
+            }
+
+            while is_incomplete(&self.buffer) && self.fill(Some(SEQUENCE_TIMEOUT)) {}
+
+            if let Some(key) = decode_key(&mut self.buffer) {
+                return Some(key);
+            }
+        }
+    }
+
+    /// Reads whatever has been typed, waiting up to `timeout` for something to
+    /// arrive, and returns whether anything did.
+    fn fill(&mut self, timeout: Option<Duration>) -> bool {
+        let mut poll_fd = libc::pollfd {
+            fd: self.tty.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        let timeout = timeout.map_or(-1, |timeout| {
+            timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int
+        });
+
+        if unsafe { libc::poll(&mut poll_fd, 1, timeout) } <= 0 {
+            return false;
+        }
+
+        let mut bytes = [0; 256];
+
+        match self.tty.read(&mut bytes) {
+            Ok(count) if count > 0 => {
+                self.buffer.extend(&bytes[..count]);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Drop for TerminalInput {
+    fn drop(&mut self) {
+        if let Err(error) = self.disable_raw_mode() {
+            eprintln!("Could not disable raw mode: {}", error);
+        }
+    }
+}
+
+/// Turns the -1 that system calls return on failure into an error.
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// Whether the front of the buffer is the start of an escape sequence or a
+/// character that hasn't fully arrived yet.
+fn is_incomplete(bytes: &VecDeque<u8>) -> bool {
+    match bytes.front() {
+        Some(&ESC) => match bytes.get(1) {
+            Some(b'[') => bytes
+                .iter()
+                .skip(2)
+                .all(|byte| is_sequence_parameter(*byte)),
+            Some(_) => false,
+            None => true,
+        },
+        Some(&first) => bytes.len() < utf8_length(first),
+        None => false,
+    }
+}
+
+/// Takes one key off the front of the buffer. Sequences for keys that all_term
+/// has no name for, like Delete, are dropped rather than typed out as text,
+/// and so are bytes that aren't valid UTF-8.
+fn decode_key(bytes: &mut VecDeque<u8>) -> Option<Key> {
+    let first = bytes.pop_front()?;
+
+    match first {
+        ESC if bytes.front() == Some(&b'[') => decode_sequence(bytes),
+        ESC => Some(Key::Escape),
+        // Terminals send one or the other for backspace.
+        0x7f | 0x08 => Some(Key::Backspace),
+        b'\r' => Some(Key::Char('\n')),
+        0..=0x7f => Some(Key::Char(char::from(first))),
+        _ => {
+            let mut encoded = vec![first];
+
+            while encoded.len() < utf8_length(first) {
+                match bytes.front() {
+                    Some(&byte @ 0x80..=0xbf) => {
+                        encoded.push(byte);
+                        bytes.pop_front();
+                    }
+                    _ => break,
+                }
+            }
+
+            let text = std::str::from_utf8(&encoded).ok()?;
+            text.chars().next().map(Key::Char)
+        }
+    }
+}
+
+/// Reads the rest of an escape sequence like `ESC [ A` for the up arrow, after
+/// the escape. If the sequence never finished, it was really Escape followed by
+/// other keys, which are left in the buffer.
+fn decode_sequence(bytes: &mut VecDeque<u8>) -> Option<Key> {
+    let end = bytes
+        .iter()
+        .skip(1)
+        .position(|byte| !is_sequence_parameter(*byte))
+        .map(|index| index + 1);
+
+    let end = match end {
+        Some(end) if (0x40..=0x7e).contains(&bytes[end]) => end,
+        _ => return Some(Key::Escape),
+    };
+
+    let sequence: Vec<u8> = bytes.drain(..=end).collect();
+    let parameters = &sequence[1..end];
+
+    match (parameters, sequence[end]) {
+        (b"", b'A') => Some(Key::Up),
+        (b"", b'B') => Some(Key::Down),
+        (b"", b'C') => Some(Key::Right),
+        (b"", b'D') => Some(Key::Left),
+        (b"", b'H') | (b"1", b'~') | (b"7", b'~') => Some(Key::Home),
+        (b"", b'F') | (b"4", b'~') | (b"8", b'~') => Some(Key::End),
+        _ => None,
+    }
+}
+
+/// Whether a byte can come between the `[` and the last byte of an escape
+/// sequence, like the `3` in `ESC [ 3 ~`.
+fn is_sequence_parameter(byte: u8) -> bool {
+    (0x20..=0x3f).contains(&byte)
+}
+
+/// How many bytes a UTF-8 character takes, going by its first byte.
+fn utf8_length(first: u8) -> usize {
+    match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::{io::Write, iter, process::Command, ptr};
+
+    /// A pseudo-terminal, returning the end that a terminal emulator would
+    /// type into along with input read from the other end.
+    fn pty() -> (File, TerminalInput) {
+        let mut typing = 0;
+        let mut tty = 0;
+
+        let result = unsafe {
+            libc::openpty(
+                &mut typing,
+                &mut tty,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+        assert_eq!(result, 0, "{}", io::Error::last_os_error());
+
+        unsafe {
+            (
+                File::from_raw_fd(typing),
+                TerminalInput::new(File::from_raw_fd(tty)),
+            )
+        }
+    }
+
+    #[test]
+    fn key_decoding() {
+        let (mut typing, mut input) = pty();
+        input.enable_raw_mode().unwrap();
+
+        // The up arrow, Escape on its own, Delete, a character in UTF-8,
+        // backspace, and return.
+        typing
+            .write_all(b"j\x1b[A\x1b\x1b[3~\xe6\x97\xa5\x7f\r\x1b[1~")
+            .unwrap();
+
+        let keys: Vec<Key> =
+            iter::from_fn(|| input.read_key(Some(Duration::from_millis(100)))).collect();
+
+        assert_eq!(
+            keys,
+            vec![
+                Key::Char('j'),
+                Key::Up,
+                Key::Escape,
+                Key::Char('日'),
+                Key::Backspace,
+                Key::Char('\n'),
+                Key::Home,
+            ]
+        );
+
+        // A sequence that never finishes was Escape and `[` typed by hand.
+        typing.write_all(b"\x1b[").unwrap();
+        assert_eq!(input.read_key(None), Some(Key::Escape));
+        assert_eq!(input.read_key(None), Some(Key::Char('[')));
+        assert_eq!(input.read_key(Some(Duration::from_millis(10))), None);
+    }
+
+    /// Keys typed while another program has the terminal go to that program.
+    /// The other half of this, all_term's thread not reading them either,
+    /// depends on `from_stdin`. That can be checked by hand: start an editor
+    /// with `e` and make sure that every key typed into it shows up there, and
+    /// that `:sh echo hi` only needs return pressed once to get back.
+    #[test]
+    fn programs_get_keys_while_we_wait() {
+        let (mut typing, mut input) = pty();
+
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg("read line; test \"$line\" = hello")
+            .stdin(input.stdin_for_child().unwrap())
+            .spawn()
+            .unwrap();
+
+        typing.write_all(b"hello\n").unwrap();
+        assert!(child.wait().unwrap().success());
+
+        input.enable_raw_mode().unwrap();
+        assert_eq!(input.read_key(Some(Duration::from_millis(10))), None);
+    }
+}
@@ -0,0 +1,271 @@
+//! A trash can following the FreeDesktop.org Trash specification:
+//! https://specifications.freedesktop.org/trash-spec/trashspec-latest.html
+//!
+//! Trashed entries are moved into `$XDG_DATA_HOME/Trash/files`, and a
+//! `.trashinfo` file with the same name in `$XDG_DATA_HOME/Trash/info`
+//! remembers where each one came from. On platforms without a FreeDesktop
+//! trash, the same layout is used inside the platform's data directory. That
+//! means that on macOS and Windows, entries trashed here don't show up in the
+//! Finder's Trash or the Recycle Bin, and only `msb` can restore them.
+
+use std::{
+    ffi::OsStr,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{date::DateTime, file_ops};
+
+/// An entry that's currently sitting in the trash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrashItem {
+    /// The name of the entry inside the trash's `files` directory.
+    pub name: String,
+
+    /// Where the entry was before it was trashed.
+    pub original_path: PathBuf,
+
+    /// When the entry was trashed, formatted like `2019-03-01T12:30:00`.
+    pub deletion_date: String,
+}
+
+#[derive(Debug)]
+pub struct Trash {
+    files_dir: PathBuf,
+    info_dir: PathBuf,
+}
+
+impl Trash {
+    /// Locates the user's trash, returning `None` if there's no data directory
+    /// to put it in. Outside of FreeDesktop platforms, this is a trash of our
+    /// own rather than the system's.
+    pub fn home() -> Option<Trash> {
+        Some(Trash::at(&dirs::data_dir()?.join("Trash")))
+    }
+
+    /// A trash kept in the given directory.
+    pub fn at(root: &Path) -> Trash {
+        Trash {
+            files_dir: root.join("files"),
+            info_dir: root.join("info"),
+        }
+    }
+
+    fn info_path(&self, name: &str) -> PathBuf {
+        self.info_dir.join(format!("{}.trashinfo", name))
+    }
+
+    /// Moves the given entry into the trash.
+    pub fn put(&self, path: &Path) -> io::Result<TrashItem> {
+        fs::create_dir_all(&self.files_dir)?;
+        fs::create_dir_all(&self.info_dir)?;
+
+        let original_path = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            std::env::current_dir()?.join(path)
+        };
+
+        let base_name = match path.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the path has no file name",
+                ))
+            }
+        };
+
+        // The spec asks for the deletion date in local time.
+        let deletion_date = DateTime::local_from_system_time(SystemTime::now()).to_iso8601();
+        let contents = format!(
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            encode_path(&original_path),
+            deletion_date
+        );
+
+        // The spec asks for the info file to be created atomically, which is
+        // also how a free name in the trash gets claimed.
+        let mut number = 1;
+        let (name, mut info_file) = loop {
+            let name = if number == 1 {
+                base_name.clone()
+            } else {
+                format!("{}.{}", base_name, number)
+            };
+
+            let info_path = self.info_path(&name);
+            let file_path = self.files_dir.join(&name);
+
+            if !file_ops::path_exists(&file_path) {
+                match OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&info_path)
+                {
+                    Ok(file) => break (name, file),
+                    Err(ref error) if error.kind() == io::ErrorKind::AlreadyExists => {}
+                    Err(error) => return Err(error),
+                }
+            }
+
+            number += 1;
+        };
+
+        let result = info_file
+            .write_all(contents.as_bytes())
+            .and_then(|_| file_ops::move_path(path, &self.files_dir.join(&name)));
+
+        if let Err(error) = result {
+            let _ = fs::remove_file(self.info_path(&name));
+            return Err(error);
+        }
+
+        Ok(TrashItem {
+            name,
+            original_path,
+            deletion_date,
+        })
+    }
+
+    /// Whether the given item is still in the trash.
+    pub fn contains(&self, item: &TrashItem) -> bool {
+        file_ops::path_exists(&self.files_dir.join(&item.name))
+            && file_ops::path_exists(&self.info_path(&item.name))
+    }
+
+    /// Lists everything in the trash, most recently trashed first.
+    pub fn list(&self) -> io::Result<Vec<TrashItem>> {
+        let mut items = Vec::new();
+
+        let read_dir = match fs::read_dir(&self.info_dir) {
+            Ok(read_dir) => read_dir,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => return Ok(items),
+            Err(error) => return Err(error),
+        };
+
+        for entry in read_dir {
+            let info_path = entry?.path();
+
+            if info_path.extension() != Some(OsStr::new("trashinfo")) {
+                continue;
+            }
+
+            let name = match info_path.file_stem() {
+                Some(stem) => stem.to_string_lossy().to_string(),
+                None => continue,
+            };
+
+            // Info files without a matching trashed entry are left over from
+            // some other program's mishap, and aren't restorable anyway.
+            if !file_ops::path_exists(&self.files_dir.join(&name)) {
+                continue;
+            }
+
+            let contents = fs::read_to_string(&info_path)?;
+
+            if let Some(item) = parse_info(name, &contents) {
+                items.push(item);
+            }
+        }
+
+        items.sort_by(|a, b| b.deletion_date.cmp(&a.deletion_date));
+
+        Ok(items)
+    }
+
+    /// Moves an entry out of the trash back to where it came from. Refuses to
+    /// replace anything that has since taken its place.
+    pub fn restore(&self, item: &TrashItem) -> io::Result<()> {
+        if file_ops::path_exists(&item.original_path) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "something else is already there",
+            ));
+        }
+
+        if let Some(parent) = item.original_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        file_ops::move_path(&self.files_dir.join(&item.name), &item.original_path)?;
+        fs::remove_file(self.info_path(&item.name))
+    }
+}
+
+fn parse_info(name: String, contents: &str) -> Option<TrashItem> {
+    let mut original_path = None;
+    let mut deletion_date = String::new();
+
+    for line in contents.lines() {
+        let mut parts = line.splitn(2, '=');
+
+        match (parts.next(), parts.next()) {
+            (Some("Path"), Some(value)) => original_path = Some(PathBuf::from(decode_path(value))),
+            (Some("DeletionDate"), Some(value)) => deletion_date = value.to_string(),
+            _ => {}
+        }
+    }
+
+    Some(TrashItem {
+        name,
+        original_path: original_path?,
+        deletion_date,
+    })
+}
+
+/// Percent-encodes a path the way the spec asks for, leaving separators and
+/// unreserved characters alone.
+fn encode_path(path: &Path) -> String {
+    let mut encoded = String::new();
+
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
+
+fn decode_path(encoded: &str) -> String {
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        if bytes[index] == b'%' && index + 2 < bytes.len() {
+            let hex = String::from_utf8_lossy(&bytes[index + 1..index + 3]).to_string();
+
+            if let Ok(byte) = u8::from_str_radix(&hex, 16) {
+                decoded.push(byte);
+                index += 3;
+                continue;
+            }
+        }
+
+        decoded.push(bytes[index]);
+        index += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn path_encoding() {
+        let path = Path::new("/home/me/some file%.txt");
+        let encoded = encode_path(path);
+
+        assert_eq!(encoded, "/home/me/some%20file%25.txt");
+        assert_eq!(decode_path(&encoded), "/home/me/some file%.txt");
+    }
+}
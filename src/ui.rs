@@ -1,7 +1,8 @@
 use crate::{
-    app_state::{find_should_match, AppState},
+    app_state::AppState,
+    config::{ColorPair, Theme},
     input_state::{InputMode, InputState},
-    terminal_context::Color,
+    overlay::{describe_exit, Overlay, OverlayContent},
     virtual_screen::VirtualScreen,
 };
 
@@ -15,6 +16,128 @@ fn pad_right_with_spaces(text: &mut String, width: usize) {
     }
 }
 
+/// Describes the selected entries for a prompt, like `2 items (foo, bar/)`.
+fn describe_selection(state: &AppState) -> String {
+    let selected = state.selected_entries();
+    let names: Vec<&str> = selected
+        .iter()
+        .map(|entry| entry.display.as_str())
+        .collect();
+    let noun = if selected.len() == 1 { "item" } else { "items" };
+
+    format!("{} {} ({})", selected.len(), noun, names.join(", "))
+}
+
+/// Lines up the metadata columns that are turned on for every entry, so each
+/// entry gets text like `1.5K  2019-03-01 12:30` to show after its name.
+fn metadata_columns(state: &AppState) -> Vec<String> {
+    let columns = state.settings.columns;
+
+    if !columns.any() {
+        return vec![String::new(); state.entries.len()];
+    }
+
+    let cells: Vec<Vec<String>> = state
+        .entries
+        .iter()
+        .map(|entry| {
+            let metadata = &entry.metadata;
+            let mut cells = Vec::new();
+
+            if columns.size {
+                cells.push(metadata.size_text());
+            }
+
+            if columns.modified {
+                cells.push(metadata.modified_text());
+            }
+
+            if columns.permissions {
+                cells.push(metadata.permissions_text());
+            }
+
+            if columns.owner {
+                cells.push(metadata.owner_text());
+            }
+
+            cells
+        })
+        .collect();
+
+    let column_count = cells.first().map(Vec::len).unwrap_or(0);
+    let widths: Vec<usize> = (0..column_count)
+        .map(|column| {
+            cells
+                .iter()
+                .map(|row| row[column].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    cells
+        .iter()
+        .map(|row| {
+            let mut text = String::new();
+
+            for (column, cell) in row.iter().enumerate() {
+                let padding = " ".repeat(widths[column] - cell.chars().count());
+
+                text.push_str("  ");
+
+                // Sizes read best lined up on the right, like in `ls -l`.
+                if columns.size && column == 0 {
+                    text.push_str(&padding);
+                    text.push_str(cell);
+                } else {
+                    text.push_str(cell);
+                    text.push_str(&padding);
+                }
+            }
+
+            text
+        })
+        .collect()
+}
+
+/// Describes the settings that change what's in the listing, for the right
+/// side of the header bar.
+fn describe_settings(state: &AppState) -> String {
+    let settings = &state.settings;
+    let mut parts = Vec::new();
+
+    parts.push(if settings.show_hidden {
+        "hidden: shown".to_string()
+    } else {
+        "hidden: not shown".to_string()
+    });
+
+    if settings.use_ignore_files {
+        parts.push("gitignore".to_string());
+    }
+
+    if !settings.ignore_patterns.is_empty() {
+        parts.push(format!("ignore: {}", settings.ignore_patterns.join(",")));
+    }
+
+    parts.push(format!("sort: {}", settings.sort.describe()));
+
+    format!(" {} ", parts.join(" | "))
+}
+
+fn adjust_window(cursor: usize, window_start: &mut usize, max_item_count: usize) {
+    let window_top = *window_start;
+    let window_bottom = *window_start + max_item_count;
+
+    if cursor <= window_top {
+        *window_start = cursor;
+    }
+
+    if cursor >= window_bottom {
+        *window_start = cursor - max_item_count + 1;
+    }
+}
+
 /// Ensures that the cursor is always on-screen by adjusting the state's
 /// windowing parameters based on the screen size.
 pub fn adjust_entry_window(state: &mut AppState, screen: &VirtualScreen) {
@@ -22,19 +145,153 @@ pub fn adjust_entry_window(state: &mut AppState, screen: &VirtualScreen) {
 
     let max_item_count = height - 4;
 
-    let window_top = state.entry_window_start;
-    let window_bottom = state.entry_window_start + max_item_count;
+    adjust_window(state.cursor, &mut state.entry_window_start, max_item_count);
 
-    if state.cursor <= window_top {
-        state.entry_window_start = state.cursor;
+    if let Some(overlay) = &mut state.overlay {
+        adjust_window(
+            overlay.cursor,
+            &mut overlay.window_start,
+            max_item_count - 2,
+        );
     }
+}
+
+/// Draws an overlay in a box on top of the directory listing.
+fn render_overlay(overlay: &Overlay, theme: &Theme, screen: &mut VirtualScreen) {
+    let (width, height) = screen.get_size();
+
+    let inner_width = width - 4;
+    let max_item_count = height - 6;
+    let lines = overlay.lines();
+    let window_start = overlay.window_start;
+    let window_end = lines.len().min(window_start + max_item_count);
+
+    let mut title = format!("+- {} ", overlay.title());
+    title.push_str(&"-".repeat((width - 2).saturating_sub(title.chars().count() + 1)));
+    title.push('+');
+    screen.write_str(1, 1, &title);
+
+    for row in 0..max_item_count {
+        let y = 2 + row;
+        let index = window_start + row;
+
+        let mut text = match lines.get(index) {
+            Some(line) if index < window_end => line.chars().take(inner_width - 2).collect(),
+            _ => String::new(),
+        };
+        pad_right_with_spaces(&mut text, inner_width - 2);
+
+        screen.write_str(1, y, "| ");
+        screen.write_str(width - 3, y, " |");
+
+        if index == overlay.cursor && index < window_end {
+            write_colored(screen, 3, y, &text, theme.cursor);
+        } else {
+            screen.write_str(3, y, &text);
+        }
+    }
+
+    let bottom = format!("+{}+", "-".repeat(width - 4));
+    screen.write_str(1, 2 + max_item_count, &bottom);
+}
+
+/// Writes a prompt followed by the text typed into it. Text too long to fit
+/// scrolls sideways half a screen at a time, keeping the cursor in view.
+fn write_prompt(
+    status_bar_text: &mut String,
+    prompt: &str,
+    input_state: &InputState,
+    screen: &mut VirtualScreen,
+) {
+    let (width, height) = screen.get_size();
+    let prompt_width = prompt.chars().count();
+    let cursor = input_state.get_cursor_position();
+
+    // The last column is left for the cursor to sit past the end of the text.
+    let room = width.saturating_sub(prompt_width + 1).max(1);
+    let step = (room / 2).max(1);
+    let scroll = if cursor < room {
+        0
+    } else {
+        ((cursor - room) / step + 1) * step
+    };
+
+    status_bar_text.push_str(prompt);
+    status_bar_text.extend(input_state.get_text_buffer().iter().skip(scroll).take(room));
+
+    screen.set_cursor_position(prompt_width + cursor - scroll, height - 1);
+}
+
+/// Adds the count and keys typed so far for something that isn't finished
+/// yet, like `3d` on the way to `3dd`.
+fn write_key_progress(status_bar_text: &mut String, input_state: &InputState) {
+    let count = input_state.get_count_progress().unwrap_or("");
+    let keys = input_state.get_pending_keys();
 
-    if state.cursor >= window_bottom {
-        state.entry_window_start = state.cursor - max_item_count + 1;
+    if !count.is_empty() || !keys.is_empty() {
+        status_bar_text.push_str(" | ");
+        status_bar_text.push_str(count);
+        status_bar_text.push_str(&keys);
     }
 }
 
-pub fn render(state: &AppState, input_state: &InputState, screen: &mut VirtualScreen) {
+/// The most rows of completions shown at once, so they don't cover the whole
+/// listing.
+const MAX_COMPLETION_ROWS: usize = 8;
+
+/// Draws the choices for tab completion in columns just above the status bar,
+/// like a shell does.
+fn render_completions(completions: &[String], screen: &mut VirtualScreen) {
+    let (width, height) = screen.get_size();
+
+    let column_width = completions
+        .iter()
+        .map(|completion| completion.chars().count() + 2)
+        .max()
+        .unwrap_or(1)
+        .min(width);
+    let columns = (width / column_width).max(1);
+    let needed_rows = completions.len().div_ceil(columns);
+    let rows = needed_rows
+        .min(MAX_COMPLETION_ROWS)
+        .min(height.saturating_sub(3));
+
+    if rows == 0 {
+        return;
+    }
+
+    let mut lines = vec![String::new(); rows];
+
+    for (index, completion) in completions.iter().take(rows * columns).enumerate() {
+        let line = &mut lines[index / columns];
+        pad_right_with_spaces(line, (index % columns) * column_width);
+        line.extend(completion.chars().take(column_width));
+    }
+
+    if needed_rows > rows {
+        let hidden = completions.len() - (rows - 1) * columns;
+        lines[rows - 1] = format!("...and {} more", hidden);
+    }
+
+    let top = height - 2 - rows;
+    screen.write_str(0, top, &"-".repeat(width));
+
+    for (row, line) in lines.iter_mut().enumerate() {
+        pad_right_with_spaces(line, width);
+        screen.write_str(0, top + 1 + row, line);
+    }
+}
+
+fn write_colored(screen: &mut VirtualScreen, x: usize, y: usize, text: &str, colors: ColorPair) {
+    screen.write_str_color(x, y, text, colors.foreground, colors.background);
+}
+
+pub fn render(
+    state: &AppState,
+    input_state: &InputState,
+    theme: &Theme,
+    screen: &mut VirtualScreen,
+) {
     let (width, height) = screen.get_size();
 
     let max_item_count = height - 4;
@@ -42,50 +299,94 @@ pub fn render(state: &AppState, input_state: &InputState, screen: &mut VirtualSc
     let window_size = max_item_count.min(state.entries.len() - window_start);
 
     let mut working_dir_text = format!("{}", state.working_directory.display());
-    pad_right_with_spaces(&mut working_dir_text, width);
-    screen.write_str_color(0, 0, &working_dir_text, Color::Black, Color::White);
 
-    let mut widest_entry_width = 0;
+    if !state.filter.is_empty() {
+        working_dir_text.push_str(&format!(" [filter: {}]", state.filter));
+    }
 
-    for (index, entry) in state.entries.iter().enumerate() {
-        widest_entry_width = widest_entry_width.max(entry.display.chars().count());
+    let settings_text = describe_settings(state);
+    pad_right_with_spaces(
+        &mut working_dir_text,
+        width.saturating_sub(settings_text.chars().count()),
+    );
+    working_dir_text.push_str(&settings_text);
+    write_colored(screen, 0, 0, &working_dir_text, theme.header);
+
+    let columns = metadata_columns(state);
+    let widest_name_width = state
+        .entries
+        .iter()
+        .map(|entry| entry.display.chars().count())
+        .max()
+        .unwrap_or(0);
+    let widest_entry_width = widest_name_width
+        + columns
+            .first()
+            .map(|text| text.chars().count())
+            .unwrap_or(0);
+
+    let find_matcher = state.find_matcher();
 
+    for (index, entry) in state.entries.iter().enumerate() {
         if index >= window_start && index < window_start + window_size {
             let y = 2 + index - window_start;
 
             enum Highlight {
                 Cursor,
                 Match,
+                Selected,
                 None,
             }
 
-            let highlight = match input_state.get_mode() {
-                InputMode::FindPrompt => {
-                    if index == state.cursor {
-                        Highlight::Cursor
-                    } else if find_should_match(&entry.display, &state.find_target) {
-                        Highlight::Match
-                    } else {
-                        Highlight::None
-                    }
-                }
-                _ => {
-                    if index == state.cursor {
-                        Highlight::Cursor
-                    } else {
-                        Highlight::None
-                    }
-                }
+            let selected = state.is_selected(entry) || state.in_visual_range(index);
+
+            if selected {
+                screen.write_str(1, y, "*");
+            }
+
+            let find_match = match input_state.get_mode() {
+                InputMode::FindPrompt => find_matcher.find(&entry.display),
+                _ => None,
+            };
+
+            // Matches that know which characters matched get just those
+            // characters highlighted, the rest get the whole row highlighted.
+            let highlight = if index == state.cursor {
+                Highlight::Cursor
+            } else if find_match
+                .as_ref()
+                .map(|found| found.positions.is_empty())
+                .unwrap_or(false)
+            {
+                Highlight::Match
+            } else if selected {
+                Highlight::Selected
+            } else {
+                Highlight::None
             };
 
+            let mut text = entry.display.clone();
+
+            if !columns[index].is_empty() {
+                pad_right_with_spaces(&mut text, widest_name_width);
+                text.push_str(&columns[index]);
+            }
+
             match highlight {
-                Highlight::Cursor => {
-                    screen.write_str_color(2, y, &entry.display, Color::Black, Color::White)
-                }
-                Highlight::Match => {
-                    screen.write_str_color(2, y, &entry.display, Color::Black, Color::Yellow)
+                Highlight::Cursor => write_colored(screen, 2, y, &text, theme.cursor),
+                Highlight::Match => write_colored(screen, 2, y, &text, theme.find_match),
+                Highlight::Selected => write_colored(screen, 2, y, &text, theme.selected),
+                Highlight::None => screen.write_str(2, y, &text),
+            }
+
+            if let Some(found) = &find_match {
+                let chars: Vec<char> = entry.display.chars().collect();
+
+                for &position in &found.positions {
+                    if let Some(char) = chars.get(position) {
+                        write_colored(screen, 2 + position, y, &char.to_string(), theme.find_match);
+                    }
                 }
-                Highlight::None => screen.write_str(2, y, &entry.display),
             }
         }
     }
@@ -111,90 +412,130 @@ pub fn render(state: &AppState, input_state: &InputState, screen: &mut VirtualSc
     screen.write_str(0, 1, top_line);
     screen.write_str(0, 2 + window_size, bottom_line);
 
-    let mut prompt_foreground = Color::Black;
-    let mut prompt_background = Color::White;
+    if let Some(overlay) = &state.overlay {
+        render_overlay(overlay, theme, screen);
+    }
+
+    render_completions(input_state.get_completions(), screen);
+
+    let mut status_colors = theme.status;
     let mut status_bar_text = String::new();
 
     match input_state.get_mode() {
         InputMode::Normal => {
-            status_bar_text.push_str("Last action: ");
+            if let Some(error) = &state.last_error {
+                status_bar_text.push_str(&error.to_string());
+
+                status_colors = theme.error;
+            } else if let Some(message) = &state.status_message {
+                status_bar_text.push_str(message);
+            } else {
+                status_bar_text.push_str("Last action: ");
+
+                match &state.last_action {
+                    Some(last_action) => {
+                        status_bar_text.push_str(&format!("{:?}", last_action));
+                    }
+                    None => status_bar_text.push_str("None"),
+                };
+            }
 
-            match &state.last_action {
-                Some(last_action) => {
-                    status_bar_text.push_str(&format!("{:?}", last_action));
-                }
-                None => status_bar_text.push_str("None"),
-            };
+            write_key_progress(&mut status_bar_text, input_state);
+        }
+        InputMode::Visual => {
+            status_bar_text.push_str(&format!(
+                "-- VISUAL -- {} selected",
+                state.selected_entries().len()
+            ));
 
-            if let Some(count) = input_state.get_count_progress() {
-                status_bar_text.push_str(" | ");
-                status_bar_text.push_str(count);
-            }
+            write_key_progress(&mut status_bar_text, input_state);
         }
-        InputMode::DeletePrompt => {
-            status_bar_text.push_str("Are you sure you want to delete selected? (y or escape)")
+        InputMode::TrashPrompt => status_bar_text.push_str(&format!(
+            "Move {} to the trash? (y or escape)",
+            describe_selection(state)
+        )),
+        InputMode::BookmarkList => {
+            status_bar_text.push_str("Bookmarks: return to jump, d to delete, escape to close");
+
+            write_key_progress(&mut status_bar_text, input_state);
         }
-        InputMode::FindPrompt => {
-            let prompt_string = "Find: ";
-            status_bar_text.push_str(prompt_string);
+        InputMode::GrepResults => {
+            status_bar_text
+                .push_str("grep: return to go to the file, e to edit it, escape to close");
 
-            for &char in input_state.get_text_buffer() {
-                status_bar_text.push(char);
+            write_key_progress(&mut status_bar_text, input_state);
+        }
+        InputMode::CommandOutput => {
+            if let Some(Overlay {
+                content: OverlayContent::Output { command, code, .. },
+                ..
+            }) = &state.overlay
+            {
+                status_bar_text.push_str(&format!("{} {} | ", command, describe_exit(*code)));
+
+                if *code != Some(0) {
+                    status_colors = theme.error;
+                }
             }
 
-            if state.no_find_match {
-                prompt_foreground = Color::White;
-                prompt_background = Color::Red;
-            }
+            status_bar_text.push_str("q or escape to close");
 
-            screen.set_cursor_position(
-                prompt_string.len() + input_state.get_cursor_position(),
-                height - 1,
-            );
+            write_key_progress(&mut status_bar_text, input_state);
         }
-        InputMode::CommandPrompt => {
-            status_bar_text.push(':');
+        InputMode::DeletePrompt => status_bar_text.push_str(&format!(
+            "Permanently delete {}? (y or escape)",
+            describe_selection(state)
+        )),
+        InputMode::TrashBrowser => {
+            status_bar_text.push_str("Trash: return or r to restore, escape to close");
+
+            write_key_progress(&mut status_bar_text, input_state);
+        }
+        InputMode::FindPrompt => {
+            let prompt_string = format!("Find ({}): ", state.settings.find_mode.name());
+            write_prompt(&mut status_bar_text, &prompt_string, input_state, screen);
 
-            for &char in input_state.get_text_buffer() {
-                status_bar_text.push(char);
+            if state.no_find_match {
+                status_colors = theme.error;
             }
-
-            screen.set_cursor_position(1 + input_state.get_cursor_position(), height - 1);
+        }
+        InputMode::FilterPrompt => {
+            let prompt_string = format!("Filter ({}): ", state.settings.find_mode.name());
+            write_prompt(&mut status_bar_text, &prompt_string, input_state, screen);
+        }
+        InputMode::FinderPrompt => {
+            write_prompt(&mut status_bar_text, "Find file: ", input_state, screen);
+        }
+        InputMode::CommandPrompt => {
+            write_prompt(&mut status_bar_text, ":", input_state, screen);
         }
         InputMode::NewFilePrompt => {
-            let prompt_string = "New file: ";
-            status_bar_text.push_str(prompt_string);
-
-            for &char in input_state.get_text_buffer() {
-                status_bar_text.push(char);
-            }
-
-            screen.set_cursor_position(
-                prompt_string.len() + input_state.get_cursor_position(),
-                height - 1,
-            );
+            write_prompt(&mut status_bar_text, "New file: ", input_state, screen);
         }
         InputMode::NewDirectoryPrompt => {
-            let prompt_string = "New dir: ";
-            status_bar_text.push_str(prompt_string);
-
-            for &char in input_state.get_text_buffer() {
-                status_bar_text.push(char);
-            }
+            write_prompt(&mut status_bar_text, "New dir: ", input_state, screen);
+        }
+        InputMode::RenamePrompt => {
+            let selected_count = state.selected_entries().len();
+            let prompt_string = if selected_count > 1 {
+                format!("Rename {} items to: ", selected_count)
+            } else {
+                "Rename to: ".to_string()
+            };
+            write_prompt(&mut status_bar_text, &prompt_string, input_state, screen);
+        }
+        InputMode::RenameOverwritePrompt => {
+            let name: String = input_state.get_text_buffer().iter().collect();
 
-            screen.set_cursor_position(
-                prompt_string.len() + input_state.get_cursor_position(),
-                height - 1,
-            );
+            status_bar_text.push_str(&format!(
+                "{} already exists. Overwrite it? (y or escape)",
+                name
+            ));
         }
+        InputMode::PasteConflictPrompt => status_bar_text
+            .push_str("Some entries already exist here. (o)verwrite, (s)kip, or (r)ename?"),
     }
 
     pad_right_with_spaces(&mut status_bar_text, width);
-    screen.write_str_color(
-        0,
-        height - 1,
-        &status_bar_text,
-        prompt_foreground,
-        prompt_background,
-    );
+    write_colored(screen, 0, height - 1, &status_bar_text, status_colors);
 }